@@ -0,0 +1,55 @@
+//! `cargo bench` harness timing both engines over the bundled puzzle sets.
+//! The `bench` subcommand runs the same measurements with more options, and
+//! can save and compare baselines.
+
+extern crate sudoku_solver;
+
+use std::fs;
+use std::path::Path;
+
+use sudoku_solver::bench::{bench_set, BenchOptions};
+use sudoku_solver::formats::{parse_sudoku, GridStrings, DETECTION_LINES};
+use sudoku_solver::{Engine, FileFormat, Solver, Sudoku};
+
+/// Grids of a file, read as the CLI reads them: in the format of its
+/// extension, or the one detected from its first lines.
+fn load_set(name: &str) -> Vec<(usize, Sudoku)> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+    let text = fs::read_to_string(&path).expect("cannot read the puzzle set");
+    let input_format = FileFormat::from_extension(&path).unwrap_or_else(|| {
+        let start = text.lines().take(DETECTION_LINES).collect::<Vec<_>>();
+        FileFormat::detect(&start.join("\n"), 9, true)
+    });
+
+    GridStrings::new(text.as_bytes(), input_format, 9)
+        .map(|grid_text| {
+            let grid_text = grid_text.expect("cannot read the puzzle set");
+            let sudoku = parse_sudoku(&grid_text.text, input_format).expect("invalid grid");
+            (grid_text.line, sudoku)
+        })
+        .collect()
+}
+
+fn main() {
+    let sets = ["top95.txt", "top87.txt", "hardest_20.txt", "grids.txt"];
+    let options = BenchOptions::default();
+
+    for engine in [Engine::Propagation, Engine::Dlx] {
+        let solver = Solver::with_engine(engine);
+        for name in sets {
+            let puzzles = load_set(name);
+            let set = bench_set(&solver, name, &puzzles, &options);
+            println!(
+                "{} {}: {} puzzles, {} runs: {}",
+                engine,
+                set.name,
+                set.puzzles.len(),
+                set.runs,
+                set.total
+            );
+            for puzzle in &set.puzzles {
+                println!("  {}:{}: {}", set.name, puzzle.line, puzzle.timings);
+            }
+        }
+    }
+}
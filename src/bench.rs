@@ -0,0 +1,224 @@
+//! Repeated timing of the solver over sets of puzzles, to measure changes to
+//! the algorithms and compare them with a saved baseline.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::solver::Solver;
+use crate::sudoku::Sudoku;
+
+/// Slowdowns under this many microseconds are timer noise, and never
+/// reported as regressions.
+const NOISE_US: u64 = 50;
+
+/// How many times each set is solved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Untimed runs first, so that the caches and threads are warm.
+    pub warmup: usize,
+    /// Timed runs, at least one.
+    pub runs: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            warmup: 3,
+            runs: 10,
+        }
+    }
+}
+
+/// Spread of the timings of repeated runs, in microseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timings {
+    pub min_us: u64,
+    pub median_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl Timings {
+    /// Summarizes `samples`, using nearest-rank percentiles. All zero when
+    /// there are none.
+    pub fn from_samples(samples: &[Duration]) -> Timings {
+        let mut us = samples
+            .iter()
+            .map(|sample| sample.as_micros() as u64)
+            .collect::<Vec<_>>();
+        if us.is_empty() {
+            return Timings::default();
+        }
+        us.sort_unstable();
+
+        let percentile = |p: usize| us[(p * us.len()).div_ceil(100).max(1) - 1];
+        Timings {
+            min_us: us[0],
+            median_us: percentile(50),
+            p99_us: percentile(99),
+            max_us: us[us.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "min {} us, median {} us, p99 {} us, max {} us",
+            self.min_us, self.median_us, self.p99_us, self.max_us
+        )
+    }
+}
+
+/// Timings of a puzzle, found at `line` of its set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PuzzleTimings {
+    pub line: usize,
+    pub timings: Timings,
+}
+
+/// Timings of a set of puzzles: of each run through the whole set, and of
+/// each puzzle.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetTimings {
+    pub name: String,
+    pub runs: usize,
+    pub total: Timings,
+    pub puzzles: Vec<PuzzleTimings>,
+}
+
+/// Solves the `puzzles` of a set, given with their line, `options.warmup`
+/// times and then `options.runs` timed times.
+pub fn bench_set(
+    solver: &Solver,
+    name: &str,
+    puzzles: &[(usize, Sudoku)],
+    options: &BenchOptions,
+) -> SetTimings {
+    for _ in 0..options.warmup {
+        for (_, sudoku) in puzzles {
+            solver.solve(sudoku);
+        }
+    }
+
+    let runs = options.runs.max(1);
+    let mut totals = Vec::with_capacity(runs);
+    let mut samples = vec![Vec::with_capacity(runs); puzzles.len()];
+    for _ in 0..runs {
+        let start = Instant::now();
+        for ((_, sudoku), samples) in puzzles.iter().zip(&mut samples) {
+            samples.push(solver.solve(sudoku).elapsed);
+        }
+        totals.push(start.elapsed());
+    }
+
+    SetTimings {
+        name: name.to_string(),
+        runs,
+        total: Timings::from_samples(&totals),
+        puzzles: puzzles
+            .iter()
+            .zip(&samples)
+            .map(|(&(line, _), samples)| PuzzleTimings {
+                line,
+                timings: Timings::from_samples(samples),
+            })
+            .collect(),
+    }
+}
+
+/// Timings saved by an earlier run, serialized as JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub sets: Vec<SetTimings>,
+}
+
+impl Baseline {
+    pub fn set(&self, name: &str) -> Option<&SetTimings> {
+        self.sets.iter().find(|set| set.name == name)
+    }
+
+    /// The sets and puzzles whose median got more than `threshold` percent
+    /// slower than in the baseline. Medians are compared, as the minimum is
+    /// too lucky and the maximum too noisy. Sets or puzzles missing from the
+    /// baseline are ignored.
+    pub fn regressions(&self, sets: &[SetTimings], threshold: f64) -> Vec<Regression> {
+        let slower = |before: &Timings, after: &Timings| {
+            after.median_us as f64 > before.median_us as f64 * (1.0 + threshold / 100.0)
+                && after.median_us > before.median_us + NOISE_US
+        };
+
+        let mut regressions = vec![];
+        for set in sets {
+            let Some(base) = self.set(&set.name) else {
+                continue;
+            };
+            if slower(&base.total, &set.total) {
+                regressions.push(Regression::new(set, None, &base.total, &set.total));
+            }
+            for puzzle in &set.puzzles {
+                let Some(before) = base.puzzles.iter().find(|p| p.line == puzzle.line) else {
+                    continue;
+                };
+                if slower(&before.timings, &puzzle.timings) {
+                    regressions.push(Regression::new(
+                        set,
+                        Some(puzzle.line),
+                        &before.timings,
+                        &puzzle.timings,
+                    ));
+                }
+            }
+        }
+        regressions
+    }
+}
+
+/// A set, or a puzzle of it at `line`, slower than in the baseline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Regression {
+    pub set: String,
+    pub line: Option<usize>,
+    /// Median of the baseline, in microseconds.
+    pub baseline_us: u64,
+    /// Median now, in microseconds.
+    pub median_us: u64,
+}
+
+impl Regression {
+    fn new(set: &SetTimings, line: Option<usize>, before: &Timings, after: &Timings) -> Regression {
+        Regression {
+            set: set.name.clone(),
+            line,
+            baseline_us: before.median_us,
+            median_us: after.median_us,
+        }
+    }
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.set)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(
+            f,
+            ": median {} us, was {} us ({:+.1}%)",
+            self.median_us,
+            self.baseline_us,
+            change_percent(self.baseline_us, self.median_us)
+        )
+    }
+}
+
+/// Relative change from `before` to `after`, in percent.
+pub fn change_percent(before: u64, after: u64) -> f64 {
+    if before == 0 {
+        return 0.0;
+    }
+    (after as f64 / before as f64 - 1.0) * 100.0
+}
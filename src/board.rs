@@ -0,0 +1,980 @@
+//! Grids of any size, such as 4x4, 6x6, 12x12, 16x16 or 25x25, with boxes of
+//! `rows` x `columns` cells. A [`Board`] builds the [`Geometry`] of its
+//! shape when it is created, and is solved by the search of the classic
+//! grid, propagating over bitmasks of up to 64 values. The classic 9x9
+//! sudoku keeps its own faster [`Grid`](crate::Grid).
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::geometry::Geometry;
+use crate::grid::{Conflict, House, ParseError, ParseErrorKind};
+use crate::solver::{Branching, SearchState, Searchable};
+
+/// Largest number of values of a board.
+pub const MAX_SIZE: usize = 64;
+
+/// Set of values of a board, stored as one bit per value.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ValueSet(u64);
+
+impl ValueSet {
+    pub const NONE: ValueSet = ValueSet(0);
+
+    /// The values of a board of `size` values.
+    pub fn all(size: usize) -> ValueSet {
+        ValueSet(u64::MAX >> (64 - size))
+    }
+
+    /// The set holding only `value`.
+    pub fn only(value: usize) -> ValueSet {
+        ValueSet(1 << value)
+    }
+
+    pub fn contains(self, value: usize) -> bool {
+        self.0 & (1 << value) != 0
+    }
+
+    pub fn insert(&mut self, value: usize) {
+        self.0 |= 1 << value;
+    }
+
+    pub fn remove(&mut self, value: usize) {
+        self.0 &= !(1 << value);
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The value of the set when it holds exactly one.
+    pub fn single(self) -> Option<usize> {
+        if self.0 != 0 && self.0 & (self.0 - 1) == 0 {
+            Some(self.0.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The smallest value of the set.
+    pub fn min(self) -> Option<usize> {
+        (self.0 != 0).then(|| self.0.trailing_zeros() as usize)
+    }
+
+    /// The largest value of the set.
+    pub fn max(self) -> Option<usize> {
+        (self.0 != 0).then(|| 63 - self.0.leading_zeros() as usize)
+    }
+
+    /// The values of the set, in increasing order.
+    pub fn iter(self) -> ValueSetIter {
+        ValueSetIter(self.0)
+    }
+}
+
+impl fmt::Debug for ValueSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(|v| v + 1)).finish()
+    }
+}
+
+impl BitOr for ValueSet {
+    type Output = ValueSet;
+
+    fn bitor(self, rhs: ValueSet) -> ValueSet {
+        ValueSet(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for ValueSet {
+    fn bitor_assign(&mut self, rhs: ValueSet) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for ValueSet {
+    type Output = ValueSet;
+
+    fn bitand(self, rhs: ValueSet) -> ValueSet {
+        ValueSet(self.0 & rhs.0)
+    }
+}
+
+/// The complement also holds the values above the size of the board, so it
+/// is meant to be intersected with another set.
+impl Not for ValueSet {
+    type Output = ValueSet;
+
+    fn not(self) -> ValueSet {
+        ValueSet(!self.0)
+    }
+}
+
+impl IntoIterator for ValueSet {
+    type Item = usize;
+    type IntoIter = ValueSetIter;
+
+    fn into_iter(self) -> ValueSetIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the values of a [`ValueSet`].
+#[derive(Clone, Debug)]
+pub struct ValueSetIter(u64);
+
+impl Iterator for ValueSetIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let value = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(value)
+    }
+}
+
+/// Shape of the boxes of a board, `rows` x `columns` cells. The board has
+/// as many rows, columns, boxes and values as a box has cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BoxShape {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl BoxShape {
+    /// The 3x3 boxes of the classic sudoku.
+    pub const CLASSIC: BoxShape = BoxShape {
+        rows: 3,
+        columns: 3,
+    };
+
+    /// Number of rows, columns and values of the board.
+    pub fn size(self) -> usize {
+        self.rows * self.columns
+    }
+
+    /// The squarest boxes of a board of `size` values, wider than tall when
+    /// they cannot be square: 2x3 for 6, 3x4 for 12, 4x4 for 16. `None`
+    /// when `size` is prime or larger than [`MAX_SIZE`].
+    pub fn for_size(size: usize) -> Option<BoxShape> {
+        if size > MAX_SIZE {
+            return None;
+        }
+        let rows = (2..=size)
+            .take_while(|rows| rows * rows <= size)
+            .filter(|&rows| size.is_multiple_of(rows))
+            .last()?;
+        Some(BoxShape {
+            rows,
+            columns: size / rows,
+        })
+    }
+}
+
+impl FromStr for BoxShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BoxShape, String> {
+        let invalid = || {
+            format!(
+                "invalid box shape '{}', expected ROWSxCOLUMNS such as 3x4",
+                s
+            )
+        };
+        let (rows, columns) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let shape = BoxShape {
+            rows: rows.trim().parse().map_err(|_| invalid())?,
+            columns: columns.trim().parse().map_err(|_| invalid())?,
+        };
+        if shape.rows == 0 || shape.columns == 0 {
+            return Err(invalid());
+        }
+        if shape.size() > MAX_SIZE {
+            return Err(format!(
+                "boxes of {} cells are too large, at most {}",
+                shape.size(),
+                MAX_SIZE
+            ));
+        }
+        Ok(shape)
+    }
+}
+
+impl fmt::Display for BoxShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.rows, self.columns)
+    }
+}
+
+/// How the values of a board are written. Empty cells are `.`, `_`, or `0`
+/// when it is not a symbol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Symbols {
+    /// `1`-`9` then letters, `1`-`9` and `A`-`G` for 16 values.
+    Digits,
+    /// `0`-`9` then letters, `0`-`9` and `A`-`F` for 16 values.
+    Hex,
+    /// Letters only, `A`-`Y` for 25 values.
+    Letters,
+    /// Numbers from 1, separated by whitespace or commas.
+    Numbers,
+}
+
+const DIGITS: &str = "123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const HEX: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+impl Symbols {
+    /// The symbols `text` is most likely written with, for a board of
+    /// `size` values. Digits are preferred when the text could be read
+    /// either way.
+    pub fn detect(text: &str, size: usize) -> Symbols {
+        let tokens = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .collect::<Vec<_>>();
+        let symbols = if tokens.len() == size * size && tokens.iter().any(|t| t.len() > 1) {
+            Symbols::Numbers
+        } else if size <= 9 {
+            Symbols::Digits
+        } else if !text.chars().any(|c| c.is_ascii_digit())
+            && text.chars().any(|c| c.is_ascii_alphabetic())
+        {
+            Symbols::Letters
+        } else {
+            // `0` is a value in hex, whose symbols stop short of the last digit one
+            let text = text.to_ascii_uppercase();
+            let last_digit = DIGITS.chars().nth(size - 1);
+            if text.contains('0')
+                && text.contains(['.', '_'])
+                && !last_digit.is_some_and(|c| text.contains(c))
+            {
+                Symbols::Hex
+            } else {
+                Symbols::Digits
+            }
+        };
+
+        if size > symbols.max_size() {
+            Symbols::Numbers
+        } else {
+            symbols
+        }
+    }
+
+    /// Largest number of values these symbols can write.
+    pub fn max_size(self) -> usize {
+        match self.alphabet() {
+            Some(alphabet) => alphabet.len(),
+            None => MAX_SIZE,
+        }
+    }
+
+    /// The symbol of each value, for single-character symbols.
+    fn alphabet(self) -> Option<&'static str> {
+        match self {
+            Symbols::Digits => Some(DIGITS),
+            Symbols::Hex => Some(HEX),
+            Symbols::Letters => Some(LETTERS),
+            Symbols::Numbers => None,
+        }
+    }
+
+    /// The symbol of `value`.
+    pub fn symbol(self, value: usize) -> String {
+        match self.alphabet() {
+            Some(alphabet) => alphabet[value..=value].to_string(),
+            None => (value + 1).to_string(),
+        }
+    }
+
+    /// Reads a cell of a board of `size` values: `Ok(None)` for an empty
+    /// cell, `Err(())` when `symbol` is not one of the values.
+    fn value(self, symbol: &str, size: usize) -> Result<Option<usize>, ()> {
+        if symbol == "." || symbol == "_" || (symbol == "0" && self != Symbols::Hex) {
+            return Ok(None);
+        }
+        let value = match self.alphabet() {
+            Some(alphabet) => alphabet.find(&symbol.to_ascii_uppercase()),
+            None => symbol.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+        };
+        match value {
+            Some(value) if value < size => Ok(Some(value)),
+            _ => Err(()),
+        }
+    }
+
+    /// Width of the widest symbol of a board of `size` values.
+    fn width(self, size: usize) -> usize {
+        match self {
+            Symbols::Numbers => size.to_string().len(),
+            Symbols::Digits | Symbols::Hex | Symbols::Letters => 1,
+        }
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Symbols, String> {
+        match s {
+            "digits" => Ok(Symbols::Digits),
+            "hex" => Ok(Symbols::Hex),
+            "letters" => Ok(Symbols::Letters),
+            "numbers" => Ok(Symbols::Numbers),
+            _ => Err(format!(
+                "unknown symbols '{}', expected digits, hex, letters or numbers",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Symbols::Digits => "digits",
+            Symbols::Hex => "hex",
+            Symbols::Letters => "letters",
+            Symbols::Numbers => "numbers",
+        })
+    }
+}
+
+/// A grid of any [`BoxShape`], with the values placed and the candidates
+/// left in the empty cells.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Board {
+    geometry: Arc<Geometry>,
+    symbols: Symbols,
+    values: Vec<Option<u8>>,
+    candidates: Vec<ValueSet>,
+}
+
+impl Board {
+    /// A board without any value, written with `symbols`.
+    ///
+    /// # Panics
+    ///
+    /// When the boxes have no cell, or more than [`MAX_SIZE`].
+    pub fn empty(shape: BoxShape, symbols: Symbols) -> Board {
+        Board::new(Arc::new(Geometry::new(shape)), symbols)
+    }
+
+    /// A board of `geometry` without any value, such as a jigsaw.
+    ///
+    /// # Panics
+    ///
+    /// When the grid has no cell, or more than [`MAX_SIZE`] values.
+    pub fn new(geometry: Arc<Geometry>, symbols: Symbols) -> Board {
+        let size = geometry.size();
+        assert!(
+            size > 0 && size <= MAX_SIZE,
+            "boards have between 1 and {} values",
+            MAX_SIZE
+        );
+        Board {
+            geometry,
+            symbols: if size > symbols.max_size() {
+                Symbols::Numbers
+            } else {
+                symbols
+            },
+            values: vec![None; size * size],
+            candidates: vec![ValueSet::all(size); size * size],
+        }
+    }
+
+    /// Parses a board of `shape` written with `symbols`, cells being
+    /// separated by whitespace or commas for [`Symbols::Numbers`] and read
+    /// one character at a time otherwise. Whitespace is ignored, so both
+    /// one-line and multi-line layouts are read. Boards too large for
+    /// `symbols` are printed with numbers.
+    pub fn parse(text: &str, shape: BoxShape, symbols: Symbols) -> Result<Board, ParseError> {
+        Board::parse_in(text, Arc::new(Geometry::new(shape)), symbols)
+    }
+
+    /// [`Board::parse`] for a board of any `geometry`.
+    pub fn parse_in(
+        text: &str,
+        geometry: Arc<Geometry>,
+        symbols: Symbols,
+    ) -> Result<Board, ParseError> {
+        let mut board = Board::new(geometry, symbols);
+        let (size, cells) = (board.size(), board.cell_count());
+
+        let mut i = 0;
+        let (mut line, mut column) = (1, 0);
+        // the symbol being read, with its line and column
+        let mut symbol = String::new();
+        let mut start = (1, 1);
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            column += 1;
+            let separator = c.is_whitespace() || (symbols == Symbols::Numbers && c == ',');
+            if !separator {
+                if symbol.is_empty() {
+                    start = (line, column);
+                }
+                symbol.push(c);
+            }
+            if c == '\n' {
+                line += 1;
+                column = 0;
+            }
+
+            let ends = match chars.peek() {
+                _ if symbol.is_empty() => false,
+                Some(&next) if symbols == Symbols::Numbers => next.is_whitespace() || next == ',',
+                _ => true,
+            };
+            if !ends {
+                continue;
+            }
+
+            let (line, column) = start;
+            let error = |kind| Err(ParseError { line, column, kind });
+            let value = match symbols.value(&symbol, size) {
+                Ok(value) => value,
+                Err(()) if symbol.chars().count() == 1 => {
+                    return error(ParseErrorKind::InvalidCharacter(c))
+                }
+                Err(()) => return error(ParseErrorKind::InvalidSymbol(symbol)),
+            };
+            if i == cells {
+                return error(ParseErrorKind::TooManyCells(cells));
+            }
+            if let Some(value) = value {
+                board.place(i, value);
+            }
+            i += 1;
+            symbol.clear();
+        }
+
+        if i < cells {
+            return Err(ParseError {
+                line,
+                column: column + 1,
+                kind: ParseErrorKind::TooFewCells(i, cells),
+            });
+        }
+
+        Ok(board)
+    }
+
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    /// Shape of the boxes, `None` for jigsaws.
+    pub fn shape(&self) -> Option<BoxShape> {
+        self.geometry.shape()
+    }
+
+    /// Number of rows, columns and values.
+    pub fn size(&self) -> usize {
+        self.geometry.size()
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn symbols(&self) -> Symbols {
+        self.symbols
+    }
+
+    pub fn value(&self, index: usize) -> Option<usize> {
+        self.values[index].map(usize::from)
+    }
+
+    /// The candidates of the cell at `index`, only its value once placed.
+    pub fn candidates(&self, index: usize) -> ValueSet {
+        self.candidates[index]
+    }
+
+    /// Number of cells holding a value.
+    pub fn value_count(&self) -> usize {
+        self.values.iter().filter(|value| value.is_some()).count()
+    }
+
+    /// Places `value` at `index` and removes it from the candidates of the
+    /// cells sharing a house with it.
+    pub fn place(&mut self, index: usize, value: usize) {
+        self.values[index] = Some(value as u8);
+        self.candidates[index] = ValueSet::only(value);
+        for &peer in self.geometry.peers(index) {
+            self.candidates[peer].remove(value);
+        }
+    }
+
+    /// Lists every pair of cells holding the same value in a house, or in a
+    /// cage without repeated values.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let geometry = &self.geometry;
+        let mut conflicts = vec![];
+        for index in 0..self.cell_count() {
+            let Some(value) = self.value(index) else {
+                continue;
+            };
+            for &house in geometry.houses_of(index) {
+                for &other in geometry.houses()[house]
+                    .iter()
+                    .filter(|&&other| other > index)
+                {
+                    if self.value(other) == Some(value) {
+                        conflicts.push(Conflict {
+                            cells: [index, other],
+                            value,
+                            house: geometry.house(house),
+                            size: self.size(),
+                        });
+                    }
+                }
+            }
+        }
+        for (number, cage) in geometry.cages().iter().enumerate() {
+            if !cage.no_repeat {
+                continue;
+            }
+            for (i, &cell) in cage.cells.iter().enumerate() {
+                let Some(value) = self.value(cell) else {
+                    continue;
+                };
+                for &other in &cage.cells[i + 1..] {
+                    // pairs sharing a house are already reported
+                    let shared = geometry
+                        .houses_of(cell)
+                        .iter()
+                        .any(|house| geometry.houses_of(other).contains(house));
+                    if self.value(other) == Some(value) && !shared {
+                        conflicts.push(Conflict {
+                            cells: [cell.min(other), cell.max(other)],
+                            value,
+                            house: House::Cage(number),
+                            size: self.size(),
+                        });
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Checks that no house of the cell at `index`, extra regions included,
+    /// holds a value twice, and that its cage does not hold one twice when
+    /// it must not, nor values over its sum, nor other values than its sum
+    /// once full.
+    pub fn check_at(&self, index: usize) -> bool {
+        let no_repeat = |cells: &[usize]| {
+            let mut seen = ValueSet::NONE;
+            cells
+                .iter()
+                .filter_map(|&cell| self.value(cell))
+                .all(|value| {
+                    let repeated = seen.contains(value);
+                    seen.insert(value);
+                    !repeated
+                })
+        };
+        let houses = self.geometry.houses_of(index);
+        if !houses
+            .iter()
+            .all(|&house| no_repeat(&self.geometry.houses()[house]))
+        {
+            return false;
+        }
+
+        let Some(cage) = self.geometry.cage_of(index) else {
+            return true;
+        };
+        let cage = &self.geometry.cages()[cage];
+        let values = cage.cells.iter().filter_map(|&cell| self.value(cell));
+        let (count, sum) = values.fold((0, 0), |(count, sum), value| (count + 1, sum + value + 1));
+        let sum_fits = if count == cage.cells.len() {
+            sum == cage.sum
+        } else {
+            sum < cage.sum
+        };
+        sum_fits && (!cage.no_repeat || no_repeat(&cage.cells))
+    }
+
+    /// Returns true when every cell holds a value and no house holds one
+    /// twice.
+    pub fn is_complete(&self) -> bool {
+        (0..self.cell_count()).all(|index| self.value(index).is_some() && self.check_at(index))
+    }
+
+    /// The board on a single line, `.` for empty cells. Numbers are
+    /// separated by spaces.
+    pub fn to_line(&self) -> String {
+        let symbols = (0..self.cell_count()).map(|index| match self.value(index) {
+            Some(value) => self.symbols.symbol(value),
+            None => ".".to_string(),
+        });
+        if self.symbols == Symbols::Numbers {
+            symbols.collect::<Vec<_>>().join(" ")
+        } else {
+            symbols.collect()
+        }
+    }
+
+    /// The board over several lines, with the candidates of the empty cells
+    /// between parentheses.
+    pub fn to_string_with_possibilities(&self) -> String {
+        self.format(true)
+    }
+
+    fn format(&self, with_possibilities: bool) -> String {
+        let size = self.size();
+        // jigsaws are printed without the lines between the boxes
+        let shape = self.shape().unwrap_or(BoxShape {
+            rows: size,
+            columns: size,
+        });
+        let width = self.symbols.width(size);
+        let separator = if self.symbols == Symbols::Numbers {
+            ","
+        } else {
+            ""
+        };
+
+        let mut output = String::new();
+        for index in 0..self.cell_count() {
+            let cell = match self.value(index) {
+                Some(value) => self.symbols.symbol(value),
+                None if with_possibilities => {
+                    let candidates = self
+                        .candidates(index)
+                        .iter()
+                        .map(|value| self.symbols.symbol(value))
+                        .collect::<Vec<_>>();
+                    format!("({})", candidates.join(separator))
+                }
+                None => "_".to_string(),
+            };
+            output.push_str(&format!("{:>width$}", cell, width = width));
+
+            let (row, column) = (index / size + 1, index % size + 1);
+            if column == size {
+                output.push('\n');
+                if row % shape.rows == 0 {
+                    output.push('\n');
+                }
+            } else if column % shape.columns == 0 {
+                output.push_str("   ");
+            } else {
+                output.push(' ');
+            }
+        }
+        output
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(false))
+    }
+}
+
+/// Fills the cells left with a single candidate and the values left with a
+/// single place in a house, and removes the candidates that cannot reach the
+/// sum of their cage, until there are none. Returns false when a cell has no
+/// candidate left, a value no place in a house, or a cage no way to reach
+/// its sum.
+fn propagate(board: &mut Board) -> bool {
+    let geometry = Arc::clone(&board.geometry);
+    let all = ValueSet::all(geometry.size());
+    loop {
+        let mut progress = false;
+
+        for index in 0..board.cell_count() {
+            if board.values[index].is_none() {
+                let candidates = board.candidates[index];
+                if candidates.is_empty() {
+                    return false;
+                }
+                if let Some(value) = candidates.single() {
+                    board.place(index, value);
+                    progress = true;
+                }
+            }
+        }
+
+        for cells in geometry.houses() {
+            let mut once = ValueSet::NONE;
+            let mut twice = ValueSet::NONE;
+            let mut placed = ValueSet::NONE;
+            for &cell in cells {
+                match board.value(cell) {
+                    Some(value) => placed.insert(value),
+                    None => {
+                        let candidates = board.candidates[cell];
+                        twice |= once & candidates;
+                        once |= candidates;
+                    }
+                }
+            }
+            if once | placed != all {
+                return false;
+            }
+
+            for value in once & !twice & !placed {
+                let cell = cells
+                    .iter()
+                    .find(|&&c| board.values[c].is_none() && board.candidates[c].contains(value));
+                if let Some(&cell) = cell {
+                    board.place(cell, value);
+                    progress = true;
+                }
+            }
+        }
+
+        for cage in 0..geometry.cages().len() {
+            match restrict_cage(board, cage) {
+                Some(removed) => progress |= removed,
+                None => return false,
+            }
+        }
+
+        if !progress {
+            return true;
+        }
+    }
+}
+
+/// Removes from the empty cells of the cage at `index` the candidates that
+/// no set of values reaching its sum uses. Returns whether candidates were
+/// removed, or `None` when the sum cannot be reached.
+fn restrict_cage(board: &mut Board, index: usize) -> Option<bool> {
+    let geometry = Arc::clone(&board.geometry);
+    let cage = &geometry.cages()[index];
+    let mut placed = ValueSet::NONE;
+    let mut placed_sum = 0;
+    let mut empty = vec![];
+    for &cell in &cage.cells {
+        match board.value(cell) {
+            Some(value) => {
+                placed.insert(value);
+                placed_sum += value + 1;
+            }
+            None => empty.push(cell),
+        }
+    }
+
+    // the values each empty cell can hold
+    let mut allowed = vec![ValueSet::NONE; empty.len()];
+    if cage.no_repeat {
+        let mut reached = false;
+        for &combination in geometry.cage_combinations(index) {
+            if combination & placed != placed {
+                continue;
+            }
+            // the values left to place, one in each empty cell
+            let left = combination & !placed;
+            if left.len() != empty.len() {
+                continue;
+            }
+            let mut covered = ValueSet::NONE;
+            let mut fits = true;
+            for &cell in &empty {
+                let candidates = board.candidates[cell] & left;
+                fits &= !candidates.is_empty();
+                covered |= candidates;
+            }
+            if fits && covered == left {
+                reached = true;
+                for (allowed, &cell) in allowed.iter_mut().zip(&empty) {
+                    *allowed |= board.candidates[cell] & left;
+                }
+            }
+        }
+        if !reached {
+            return None;
+        }
+    } else {
+        let bounds = empty
+            .iter()
+            .map(|&cell| {
+                let candidates = board.candidates[cell];
+                Some((candidates.min()? + 1, candidates.max()? + 1))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let lowest = placed_sum + bounds.iter().map(|&(min, _)| min).sum::<usize>();
+        let highest = placed_sum + bounds.iter().map(|&(_, max)| max).sum::<usize>();
+        if !(lowest..=highest).contains(&cage.sum) {
+            return None;
+        }
+        for ((allowed, &cell), &(min, max)) in allowed.iter_mut().zip(&empty).zip(&bounds) {
+            for value in board.candidates[cell] {
+                // the other cells can make up for this value
+                let (low, high) = (lowest - min + value + 1, highest - max + value + 1);
+                if (low..=high).contains(&cage.sum) {
+                    allowed.insert(value);
+                }
+            }
+        }
+    }
+
+    let mut removed = false;
+    for (allowed, &cell) in allowed.into_iter().zip(&empty) {
+        let candidates = board.candidates[cell] & allowed;
+        removed |= candidates != board.candidates[cell];
+        board.candidates[cell] = candidates;
+    }
+    Some(removed)
+}
+
+/// [`propagate`], counting the cells it fills and whether it hit a dead
+/// end.
+impl Searchable for Board {
+    type Values = ValueSet;
+
+    fn propagate_givens(&mut self, _state: &SearchState) -> bool {
+        // the candidates follow the values placed, and the first node
+        // propagates them
+        true
+    }
+
+    fn propagate(&mut self, state: &SearchState) -> bool {
+        let before = self.value_count();
+        let possible = propagate(self);
+        state.count_propagation(self.value_count() - before, possible);
+        possible
+    }
+
+    fn guessed_cell(&self, branching: Branching) -> Option<(usize, ValueSet)> {
+        let mut empty = (0..self.cell_count())
+            .filter(|&index| self.values[index].is_none())
+            .map(|index| (index, self.candidates[index]));
+        match branching {
+            Branching::Parallel | Branching::Sequential => {
+                empty.min_by_key(|&(_, candidates)| candidates.len())
+            }
+            Branching::Ordered => empty.next(),
+        }
+    }
+
+    fn place_guess(&mut self, index: usize, value: usize, state: &SearchState) -> bool {
+        state.guess();
+        self.place(index, value);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{Solver, SolverConfig};
+
+    fn shape(rows: usize, columns: usize) -> BoxShape {
+        BoxShape { rows, columns }
+    }
+
+    /// A full board of `shape`, always the same one.
+    fn full_board(shape: BoxShape, symbols: Symbols) -> Board {
+        let solver = Solver::with_config(SolverConfig {
+            parallel_branches: false,
+            ..SolverConfig::default()
+        });
+        let result = solver.solve_board(&Board::empty(shape, symbols));
+        result.solution().unwrap().clone()
+    }
+
+    /// The values of `full` in the cells `keep` holds.
+    fn puzzle(full: &Board, keep: impl Fn(usize) -> bool) -> Board {
+        let mut puzzle = Board::new(Arc::clone(&full.geometry), full.symbols());
+        for index in (0..full.cell_count()).filter(|&index| keep(index)) {
+            puzzle.place(index, full.value(index).unwrap());
+        }
+        puzzle
+    }
+
+    #[test]
+    fn boards_parse_and_solve() {
+        let boards = [
+            (shape(2, 2), Symbols::Digits),
+            (shape(2, 3), Symbols::Digits),
+            (shape(3, 2), Symbols::Digits),
+            (shape(4, 4), Symbols::Digits),
+            (shape(4, 4), Symbols::Hex),
+            (shape(5, 5), Symbols::Letters),
+            (shape(3, 4), Symbols::Numbers),
+            (shape(5, 5), Symbols::Numbers),
+        ];
+        for (shape, symbols) in boards {
+            let full = full_board(shape, symbols);
+            assert!(full.is_complete());
+            let puzzle = puzzle(&full, |index| index % 2 == 0);
+
+            let line = puzzle.to_line();
+            assert_eq!(Symbols::detect(&line, shape.size()), symbols, "{}", line);
+            let parsed = Board::parse(&line, shape, symbols).unwrap();
+            assert_eq!(parsed, puzzle, "{} {}", shape, symbols);
+            let grid = puzzle.to_string();
+            assert_eq!(Board::parse(&grid, shape, symbols).unwrap(), puzzle);
+
+            let result = Solver::new().solve_board(&parsed);
+            let solution = result.solution().unwrap();
+            assert!(solution.is_complete(), "{} {}", shape, symbols);
+            for index in (0..puzzle.cell_count()).step_by(2) {
+                assert_eq!(solution.value(index), puzzle.value(index));
+            }
+            let count = Solver::new().count_board_solutions(&parsed, 2);
+            assert!(count.count >= 1);
+        }
+    }
+
+    #[test]
+    fn numbers_are_separated() {
+        let full = full_board(shape(3, 4), Symbols::Numbers);
+        let line = puzzle(&full, |index| index % 3 == 0).to_line();
+        let commas = line.replace(' ', ",");
+        assert_eq!(Symbols::detect(&commas, 12), Symbols::Numbers);
+        assert_eq!(
+            Board::parse(&commas, shape(3, 4), Symbols::Numbers).unwrap(),
+            Board::parse(&line, shape(3, 4), Symbols::Numbers).unwrap()
+        );
+        let err = Board::parse(&line.replacen("12", "13", 1), shape(3, 4), Symbols::Numbers);
+        assert_eq!(
+            err.unwrap_err().kind,
+            ParseErrorKind::InvalidSymbol("13".to_string())
+        );
+    }
+
+    #[test]
+    fn detection_of_digits_only() {
+        // 16x16 grids holding only 1-9 are read with digits, whatever the
+        // cells given and the empty cells are written with
+        let full = full_board(shape(4, 4), Symbols::Digits);
+        for keep in [2, 3, 5] {
+            let puzzle = puzzle(&full, |index| {
+                index % keep == 0 && full.value(index).unwrap() < 9
+            });
+            let line = puzzle.to_line();
+            assert!(line.chars().all(|c| c == '.' || ('1'..='9').contains(&c)));
+            for text in [line.clone(), line.replace('.', "0"), line.replace('.', "_")] {
+                assert_eq!(Symbols::detect(&text, 16), Symbols::Digits, "{}", text);
+                assert_eq!(
+                    Board::parse(&text, shape(4, 4), Symbols::Digits).unwrap(),
+                    puzzle
+                );
+            }
+        }
+        // `0` being a value of hex, it is read as such next to other empty
+        // cells
+        assert_eq!(
+            Symbols::detect(&format!("0.{}", "1".repeat(254)), 16),
+            Symbols::Hex
+        );
+    }
+}
@@ -1,3 +1,6 @@
+//! The classic peer tables as they were written by hand, kept to check that
+//! the generated geometry reproduces them exactly.
+
 pub const ADJACENT_CELLS: [[[usize; 8]; 3]; 81] = [
     [
         [1, 2, 3, 4, 5, 6, 7, 8],
@@ -0,0 +1,390 @@
+//! Exact cover solver using Knuth's Dancing Links (Algorithm X).
+//!
+//! A sudoku is encoded as an exact cover problem with 324 constraints: each
+//! cell holds a value, and each row, column and box holds each value once.
+//! Every choice of a value for a cell is a row covering four of them.
+
+use crate::geometry::houses_of;
+use crate::grid::Grid;
+use crate::solver::{Branching, SearchState, SolutionCount, SolveStats};
+use crate::sudoku::Sudoku;
+
+const CONSTRAINTS: usize = 4 * 81;
+
+/// Sparse 0/1 matrix stored as circular doubly linked lists, whose exact
+/// covers are subsets of rows holding exactly one 1 in each column.
+#[derive(Clone, Debug)]
+pub struct ExactCover {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    /// Column header of each node.
+    column: Vec<usize>,
+    /// Row id of each node, given to `add_row`.
+    row: Vec<usize>,
+    /// Number of nodes in each column.
+    size: Vec<usize>,
+    /// Counters of the last `solve`.
+    stats: SolveStats,
+}
+
+/// Index of the root header, the columns headers follow it.
+const ROOT: usize = 0;
+
+impl ExactCover {
+    pub fn new(columns: usize) -> ExactCover {
+        let headers = columns + 1;
+        let mut matrix = ExactCover {
+            left: (0..headers).map(|i| (i + headers - 1) % headers).collect(),
+            right: (0..headers).map(|i| (i + 1) % headers).collect(),
+            up: (0..headers).collect(),
+            down: (0..headers).collect(),
+            column: (0..headers).collect(),
+            row: vec![usize::MAX; headers],
+            size: vec![0; headers],
+            stats: SolveStats::default(),
+        };
+        matrix.size[ROOT] = usize::MAX;
+        matrix
+    }
+
+    /// Adds a row with a 1 in each of `columns`, numbered from 0.
+    pub fn add_row(&mut self, row: usize, columns: &[usize]) {
+        let first = self.left.len();
+        for (k, &c) in columns.iter().enumerate() {
+            let header = c + 1;
+            let node = first + k;
+
+            self.column.push(header);
+            self.row.push(row);
+            self.up.push(self.up[header]);
+            self.down.push(header);
+            let last = self.up[header];
+            self.down[last] = node;
+            self.up[header] = node;
+            self.size[header] += 1;
+
+            self.left.push(if k == 0 { node } else { node - 1 });
+            self.right.push(first);
+            if k > 0 {
+                self.right[node - 1] = node;
+                self.left[first] = node;
+            }
+        }
+    }
+
+    /// Calls `on_solution` with the row ids of each exact cover, until
+    /// `limit` are found. Returns the number of covers found.
+    pub fn solve<F: FnMut(&[usize])>(&mut self, limit: usize, on_solution: F) -> usize {
+        self.solve_with(limit, false, &|| false, on_solution)
+    }
+
+    /// Like [`ExactCover::solve`], but covers the leftmost column first
+    /// unless another one is forced, and tries its rows in the order they
+    /// were added, so that the covers are found in the lexicographic order of
+    /// their rows. Slower, as the search tree gets larger.
+    pub fn solve_in_order<F: FnMut(&[usize])>(&mut self, limit: usize, on_solution: F) -> usize {
+        self.solve_with(limit, true, &|| false, on_solution)
+    }
+
+    /// The search gives up as soon as `stop` returns true.
+    fn solve_with<F: FnMut(&[usize])>(
+        &mut self,
+        limit: usize,
+        leftmost: bool,
+        stop: &dyn Fn() -> bool,
+        mut on_solution: F,
+    ) -> usize {
+        let mut search = Search {
+            partial: vec![],
+            limit,
+            leftmost,
+            stop,
+            found: 0,
+            depth: 0,
+            on_solution: &mut on_solution,
+        };
+        self.stats = SolveStats::default();
+        self.search(&mut search);
+        search.found
+    }
+
+    /// Counters of the last [`ExactCover::solve`]: guesses are the rows
+    /// tried in a column that several rows could cover, and the propagated
+    /// cells the rows chosen because they were the only ones left in their
+    /// column.
+    pub fn stats(&self) -> SolveStats {
+        self.stats
+    }
+
+    fn search<F: FnMut(&[usize])>(&mut self, search: &mut Search<F>) {
+        if search.is_over() {
+            return;
+        }
+        self.stats.nodes += 1;
+        self.stats.max_depth = self.stats.max_depth.max(search.depth);
+        if self.right[ROOT] == ROOT {
+            search.found += 1;
+            (search.on_solution)(&search.partial);
+            return;
+        }
+
+        // the column with the fewest rows keeps the tree small
+        let mut c = self.right[ROOT];
+        let mut j = self.right[c];
+        while j != ROOT {
+            if self.size[j] < self.size[c] {
+                c = j;
+            }
+            j = self.right[j];
+        }
+        // when covering in order, only a column with a single row is worth
+        // covering first: it is forced and does not change the order
+        if search.leftmost && self.size[c] > 1 {
+            c = self.right[ROOT];
+        }
+
+        let guessing = match self.size[c] {
+            0 => {
+                self.stats.dead_ends += 1;
+                return;
+            }
+            1 => {
+                self.stats.propagated += 1;
+                false
+            }
+            _ => true,
+        };
+
+        self.cover(c);
+        let mut r = self.down[c];
+        while r != c && !search.is_over() {
+            if guessing {
+                self.stats.guesses += 1;
+                search.depth += 1;
+            }
+            search.partial.push(self.row[r]);
+            let mut j = self.right[r];
+            while j != r {
+                self.cover(self.column[j]);
+                j = self.right[j];
+            }
+
+            self.search(search);
+            if guessing {
+                search.depth -= 1;
+            }
+
+            let mut j = self.left[r];
+            while j != r {
+                self.uncover(self.column[j]);
+                j = self.left[j];
+            }
+            search.partial.pop();
+            r = self.down[r];
+        }
+        self.uncover(c);
+    }
+
+    fn cover(&mut self, c: usize) {
+        let (l, r) = (self.left[c], self.right[c]);
+        self.right[l] = r;
+        self.left[r] = l;
+
+        let mut i = self.down[c];
+        while i != c {
+            let mut j = self.right[i];
+            while j != i {
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = d;
+                self.up[d] = u;
+                self.size[self.column[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, c: usize) {
+        let mut i = self.up[c];
+        while i != c {
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.column[j]] += 1;
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = j;
+                self.up[d] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+
+        let (l, r) = (self.left[c], self.right[c]);
+        self.right[l] = c;
+        self.left[r] = c;
+    }
+}
+
+/// State of a running [`ExactCover::solve`].
+struct Search<'a, F> {
+    /// Rows of the cover being built.
+    partial: Vec<usize>,
+    limit: usize,
+    leftmost: bool,
+    stop: &'a dyn Fn() -> bool,
+    found: usize,
+    /// Guesses the current cover is built on.
+    depth: usize,
+    on_solution: &'a mut F,
+}
+
+impl<F> Search<'_, F> {
+    fn is_over(&self) -> bool {
+        self.found >= self.limit || (self.stop)()
+    }
+}
+
+/// Builds the exact cover matrix of a grid. Cells holding a value only get
+/// the row of that value, empty cells one row per possibility. Row ids are
+/// `cell * 9 + value`.
+fn sudoku_matrix(grid: &Grid) -> ExactCover {
+    let mut matrix = ExactCover::new(CONSTRAINTS);
+    for index in 0..81 {
+        let values = match grid.value(index) {
+            Some(value) => vec![value],
+            None => (grid.candidates(index) & grid.allowed(index))
+                .iter()
+                .collect(),
+        };
+        // after the 81 cell constraints come the 27 houses, 9 values each
+        let [row, column, block] = houses_of(index);
+        for value in values {
+            matrix.add_row(
+                index * 9 + value,
+                &[
+                    index,
+                    81 + row * 9 + value,
+                    81 + column * 9 + value,
+                    81 + block * 9 + value,
+                ],
+            );
+        }
+    }
+    matrix
+}
+
+/// The statistics of the matrix of `grid`, without the rows of the givens
+/// that are always chosen first as they are alone in their column.
+fn sudoku_stats(matrix: &ExactCover, grid: &Grid) -> SolveStats {
+    let stats = matrix.stats();
+    SolveStats {
+        propagated: stats.propagated.saturating_sub(grid.value_count()),
+        ..stats
+    }
+}
+
+fn grid_from_rows(rows: &[usize]) -> Grid {
+    let mut grid = Grid::empty();
+    for &row in rows {
+        grid.place(row / 9, row % 9);
+    }
+    grid
+}
+
+/// Solves a grid with Dancing Links, returning the first solution found or
+/// `None` when the givens admit no solution.
+pub fn solve_grid_dlx(grid: Grid) -> Option<Grid> {
+    search_grid_dlx(grid, &SearchState::new(Branching::Sequential))
+}
+
+/// [`solve_grid_dlx`], following `state`. With [`Branching::Ordered`] the
+/// solution is the lexicographically smallest one: as the cell constraints
+/// come first and the rows of a cell are in increasing value order, covering
+/// the leftmost column fills the cells in order.
+pub(crate) fn search_grid_dlx(grid: Grid, state: &SearchState) -> Option<Grid> {
+    let ordered = state.branching == Branching::Ordered;
+    let mut solution = None;
+    let mut matrix = sudoku_matrix(&grid);
+    matrix.solve_with(1, ordered, &|| state.is_interrupted(), |rows| {
+        solution = Some(grid_from_rows(rows))
+    });
+    state.add_stats(&sudoku_stats(&matrix, &grid));
+    solution
+}
+
+/// Counts the solutions of a grid with Dancing Links, stopping once `limit`
+/// are found.
+pub fn count_solutions_dlx(grid: Grid, limit: usize) -> SolutionCount {
+    search_solution_count_dlx(grid, limit, &SearchState::new(Branching::Sequential))
+}
+
+/// [`count_solutions_dlx`], following `state`.
+pub(crate) fn search_solution_count_dlx(
+    grid: Grid,
+    limit: usize,
+    state: &SearchState,
+) -> SolutionCount {
+    let ordered = state.branching == Branching::Ordered;
+    let mut solutions = vec![];
+    let mut matrix = sudoku_matrix(&grid);
+    let count = matrix.solve_with(limit, ordered, &|| state.is_interrupted(), |rows| {
+        if solutions.len() < 2 {
+            solutions.push(Sudoku::from_grid(grid_from_rows(rows)));
+        }
+    });
+    state.add_stats(&sudoku_stats(&matrix, &grid));
+
+    SolutionCount {
+        count,
+        limit,
+        solutions,
+        stats: state.stats(),
+        interrupted: state.interruption(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::parse_grid;
+    use crate::solver::{search_grid, search_solution_count};
+
+    const UNIQUE: &str =
+        "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......";
+    // UNIQUE without its r5c7
+    const SEVERAL: &str =
+        "4.....8.5.3..........7......2.....6.....8........1.......6.3.7.5..2.....1.4......";
+    // r1c9 has no value left, though no house repeats a given
+    const CONTRADICTORY: &str =
+        "12345678.........9...............................................................";
+
+    fn ordered() -> SearchState {
+        SearchState::new(Branching::Ordered)
+    }
+
+    #[test]
+    fn dlx_finds_the_solutions_of_propagation() {
+        for text in [UNIQUE, SEVERAL, CONTRADICTORY] {
+            let grid = parse_grid(text).unwrap();
+            assert_eq!(
+                search_grid_dlx(grid, &ordered()),
+                search_grid(grid, &ordered()),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn dlx_counts_the_solutions_of_propagation() {
+        for (text, count) in [(UNIQUE, 1), (SEVERAL, 794), (CONTRADICTORY, 0)] {
+            let grid = parse_grid(text).unwrap();
+            let dlx = search_solution_count_dlx(grid, 1000, &ordered());
+            let propagation = search_solution_count(grid, 1000, &ordered());
+            assert_eq!(dlx.count, count, "{}", text);
+            assert_eq!(propagation.count, count, "{}", text);
+            assert_eq!(dlx.solutions, propagation.solutions, "{}", text);
+        }
+    }
+}
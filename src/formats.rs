@@ -0,0 +1,678 @@
+//! Puzzle files: the layouts grids are written in, detected from the
+//! extension or the first lines of a file, with the metadata given with each
+//! grid, and the texts of jigsaws and killer cages.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::grid::ParseError;
+use crate::sudoku::Sudoku;
+
+/// Layout of the grids in a puzzle file.
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileFormat {
+    /// One grid of 81 characters per line
+    OneLiner,
+    /// Grids over 9 lines
+    MultiLine,
+    /// Grids over 9 lines giving the candidates of the empty cells, as
+    /// digit groups or between parentheses
+    PencilMarks,
+    /// SudoCue .sdk: grids over 9 lines, after `#` metadata lines such as
+    /// `#A` for the author
+    Sdk,
+    /// SudoCue .sdm: one grid of 81 digits per line, 0 for empty cells
+    Sdm,
+    /// SadMan Software .sudoku: grids over 9 lines in `[Puzzle]` sections
+    Sadman,
+    /// Simple Sudoku .ss: grids over 9 lines, with `|` between the boxes and
+    /// `-` lines between the bands
+    SimpleSudoku,
+}
+
+/// Letters of the SDK metadata lines, and the keys they are read as.
+const SDK_METADATA: [(char, &str); 7] = [
+    ('A', "author"),
+    ('B', "date"),
+    ('C', "comment"),
+    ('D', "description"),
+    ('L', "level"),
+    ('S', "source"),
+    ('U', "url"),
+];
+
+/// Lines read to detect the format of a file without a known extension.
+pub const DETECTION_LINES: usize = 20;
+
+impl FileFormat {
+    /// Every format, in the order they are listed in.
+    pub const ALL: [FileFormat; 7] = [
+        FileFormat::OneLiner,
+        FileFormat::MultiLine,
+        FileFormat::PencilMarks,
+        FileFormat::Sdk,
+        FileFormat::Sdm,
+        FileFormat::Sadman,
+        FileFormat::SimpleSudoku,
+    ];
+
+    /// Name of the format on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::OneLiner => "one-liner",
+            FileFormat::MultiLine => "multi-line",
+            FileFormat::PencilMarks => "pencil-marks",
+            FileFormat::Sdk => "sdk",
+            FileFormat::Sdm => "sdm",
+            FileFormat::Sadman => "sadman",
+            FileFormat::SimpleSudoku => "simple-sudoku",
+        }
+    }
+
+    /// One line description of the format.
+    pub fn description(self) -> &'static str {
+        match self {
+            FileFormat::OneLiner => "One grid of 81 characters per line",
+            FileFormat::MultiLine => "Grids over 9 lines",
+            FileFormat::PencilMarks => {
+                "Grids over 9 lines giving the candidates of the empty cells, as digit groups or between parentheses"
+            }
+            FileFormat::Sdk => {
+                "SudoCue .sdk: grids over 9 lines, after `#` metadata lines such as `#A` for the author"
+            }
+            FileFormat::Sdm => "SudoCue .sdm: one grid of 81 digits per line, 0 for empty cells",
+            FileFormat::Sadman => "SadMan Software .sudoku: grids over 9 lines in `[Puzzle]` sections",
+            FileFormat::SimpleSudoku => {
+                "Simple Sudoku .ss: grids over 9 lines, with `|` between the boxes and `-` lines between the bands"
+            }
+        }
+    }
+
+    /// Number of lines of a grid spread over `lines` lines in the
+    /// multi-line formats.
+    pub fn rows(self, lines: usize) -> usize {
+        match self {
+            FileFormat::OneLiner | FileFormat::Sdm => 1,
+            _ => lines,
+        }
+    }
+
+    /// Whether grids of other sizes than 9x9, or with regions, can be
+    /// written in the format.
+    pub fn any_size(self) -> bool {
+        self == FileFormat::OneLiner || self == FileFormat::MultiLine
+    }
+
+    /// The format of a file with the extension of `path`, when it has one
+    /// of its own.
+    pub fn from_extension(path: &Path) -> Option<FileFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "sdk" => Some(FileFormat::Sdk),
+            "sdm" => Some(FileFormat::Sdm),
+            "sudoku" => Some(FileFormat::Sadman),
+            "ss" => Some(FileFormat::SimpleSudoku),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a file of grids of `size` rows from its first
+    /// lines, among the one-liner and multi-line formats only unless
+    /// `all_formats`.
+    pub fn detect(start: &str, size: usize, all_formats: bool) -> FileFormat {
+        let mut metadata = false;
+        for line in start.lines().map(str::trim) {
+            if let Some(comment) = line.strip_prefix('#') {
+                metadata |= comment.starts_with(|c: char| c.is_ascii_uppercase());
+                continue;
+            }
+            let line = line.split('#').next().unwrap_or_default();
+            if line.starts_with('[') {
+                return FileFormat::Sadman;
+            }
+            if line.contains('(') {
+                return FileFormat::PencilMarks;
+            }
+            // empty lines and borders
+            if line
+                .chars()
+                .all(|c| c.is_whitespace() || "|-+*.:'=".contains(c))
+            {
+                continue;
+            }
+
+            // a line holds all the cells, as characters or numbers
+            let cells = size * size;
+            let numbers = line.split([' ', '\t', ',']).filter(|n| !n.is_empty());
+            if line.split_whitespace().next().map_or(0, str::len) >= cells
+                || numbers.count() >= cells
+            {
+                return FileFormat::OneLiner;
+            }
+            if !all_formats {
+                return FileFormat::MultiLine;
+            }
+            let candidates = line
+                .split(|c: char| c.is_whitespace() || "|-+.:'=".contains(c))
+                .any(|cell| cell.len() > 1 && cell.chars().all(|c| c.is_ascii_digit()));
+            if candidates && line.contains(char::is_whitespace) {
+                return FileFormat::PencilMarks;
+            }
+            if line.contains('|') {
+                return FileFormat::SimpleSudoku;
+            }
+            return if metadata {
+                FileFormat::Sdk
+            } else {
+                FileFormat::MultiLine
+            };
+        }
+        FileFormat::OneLiner
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FileFormat, String> {
+        FileFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                let names = FileFormat::ALL.map(FileFormat::name);
+                format!(
+                    "unknown format '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Writes `sudoku` and its `metadata` in `format`, which reads them back.
+pub fn write_sudoku<W: Write>(
+    format: FileFormat,
+    sudoku: &Sudoku,
+    metadata: &[(String, String)],
+    out: &mut W,
+) -> io::Result<()> {
+    let line = sudoku.to_line();
+    let rows = line
+        .as_bytes()
+        .chunks(9)
+        .map(|row| String::from_utf8_lossy(row));
+    // the metadata goes after the first row, as a comment
+    let comment = metadata_comment(metadata);
+
+    match format {
+        FileFormat::OneLiner => writeln!(out, "{}{}", line, comment),
+        FileFormat::Sdm => writeln!(out, "{}{}", line.replace('.', "0"), comment),
+        FileFormat::MultiLine => write!(out, "{}", commented(&sudoku.to_string(), &comment)),
+        FileFormat::PencilMarks => write!(
+            out,
+            "{}",
+            commented(&sudoku.to_string_with_possibilities(), &comment)
+        ),
+        FileFormat::Sdk => {
+            // the keys without a letter stay in a comment
+            let mut others = vec![];
+            for (key, value) in metadata {
+                match SDK_METADATA.iter().find(|&&(_, name)| name == key) {
+                    Some(&(letter, _)) => writeln!(out, "#{}{}", letter, value)?,
+                    None if key.chars().count() == 1 => writeln!(out, "#{}{}", key, value)?,
+                    None => others.push((key.clone(), value.clone())),
+                }
+            }
+            for (i, row) in rows.enumerate() {
+                if i == 0 && !others.is_empty() {
+                    writeln!(out, "{} # {}", row, metadata_text(&others))?;
+                } else {
+                    writeln!(out, "{}", row)?;
+                }
+            }
+            writeln!(out)
+        }
+        FileFormat::Sadman => {
+            writeln!(out, "[Puzzle]")?;
+            let grid = rows.collect::<Vec<_>>().join("\n");
+            writeln!(out, "{}", commented(&grid, &comment))?;
+            writeln!(out)
+        }
+        FileFormat::SimpleSudoku => {
+            let mut grid = String::new();
+            for (i, row) in rows.enumerate() {
+                if i == 3 || i == 6 {
+                    grid.push_str("-----------\n");
+                }
+                grid.push_str(&format!("{}|{}|{}\n", &row[..3], &row[3..6], &row[6..]));
+            }
+            writeln!(out, "{}", commented(&grid, &comment))
+        }
+    }
+}
+
+/// A grid over several lines, with `comment` at the end of the first one.
+pub fn commented(grid: &str, comment: &str) -> String {
+    match grid.split_once('\n') {
+        Some((first, rest)) => format!("{}{}\n{}", first, comment, rest),
+        None => format!("{}{}", grid, comment),
+    }
+}
+
+/// The metadata as a comment to append to a line, empty without any.
+pub fn metadata_comment(metadata: &[(String, String)]) -> String {
+    if metadata.is_empty() {
+        String::new()
+    } else {
+        format!(" # {}", metadata_text(metadata))
+    }
+}
+
+/// Metadata in the `name=Golden Nugget rating=11.9` notation.
+pub fn metadata_text(metadata: &[(String, String)]) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Metadata of a `name=Golden Nugget rating=11.9` comment: each `key=`
+/// starts a value running to the next key. Words before the first key are
+/// kept as a `comment`.
+pub fn comment_metadata(comment: &str) -> Vec<(String, String)> {
+    let mut metadata: Vec<(String, String)> = vec![];
+    for word in comment.split_whitespace() {
+        match word.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                metadata.push((key.to_string(), value.to_string()))
+            }
+            _ => match metadata.last_mut() {
+                Some((_, value)) => {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(word);
+                }
+                None => metadata.push(("comment".to_string(), word.to_string())),
+            },
+        }
+    }
+    metadata
+}
+
+/// A grid read from an input, with the line it starts at and the metadata
+/// given before it.
+pub struct GridText {
+    /// Line the grid starts at, from 1.
+    pub line: usize,
+    pub text: String,
+    pub metadata: Vec<(String, String)>,
+}
+
+/// What a line of a puzzle file holds.
+enum Line {
+    /// Nothing, or only what is drawn around the grids.
+    Blank,
+    /// Cells of a grid.
+    Row(String),
+    Metadata(String, String),
+}
+
+/// Splits an input into grids, reading only the lines of the grid being
+/// returned.
+pub struct GridStrings<R> {
+    lines: io::Lines<R>,
+    input_format: FileFormat,
+    /// Lines of a grid in the multi-line formats.
+    grid_lines: usize,
+    line: usize,
+    /// Whether the lines are in the `[Puzzle]` section of a SadMan file.
+    in_puzzle: bool,
+    /// Metadata read for the next grid.
+    metadata: Vec<(String, String)>,
+}
+
+impl<R: BufRead> GridStrings<R> {
+    /// Reads the grids of `input` written in `input_format`, spread over
+    /// `grid_lines` lines in the multi-line formats.
+    pub fn new(input: R, input_format: FileFormat, grid_lines: usize) -> GridStrings<R> {
+        GridStrings {
+            lines: input.lines(),
+            input_format,
+            grid_lines,
+            line: 0,
+            in_puzzle: true,
+            metadata: vec![],
+        }
+    }
+
+    fn classify(&mut self, mut line: String) -> Line {
+        // what follows a `#` after the cells is the metadata of the grid
+        if let Some(start) = line
+            .find('#')
+            .filter(|&start| !line[..start].trim().is_empty())
+        {
+            self.metadata.extend(comment_metadata(&line[start + 1..]));
+            line.truncate(start);
+        }
+        let content = line.trim();
+        if content.is_empty() {
+            return Line::Blank;
+        }
+
+        match self.input_format {
+            // the borders drawn around pencil marks have no candidates
+            FileFormat::PencilMarks
+                if !content.contains(|c: char| c.is_ascii_digit() || c == '(') =>
+            {
+                Line::Blank
+            }
+            FileFormat::Sdk if content.starts_with('#') => match sdk_metadata(&content[1..]) {
+                Some((key, value)) => Line::Metadata(key, value),
+                None => Line::Blank,
+            },
+            FileFormat::Sadman if content.starts_with('[') => {
+                self.in_puzzle = content.eq_ignore_ascii_case("[puzzle]");
+                Line::Blank
+            }
+            FileFormat::Sadman if !self.in_puzzle => Line::Blank,
+            // other lines starting with `#` are comments
+            _ if content.starts_with('#') => Line::Blank,
+            FileFormat::SimpleSudoku
+                if content
+                    .chars()
+                    .all(|c| c.is_whitespace() || "-+*|".contains(c)) =>
+            {
+                Line::Blank
+            }
+            // blanks keep the columns of the cells for the errors
+            FileFormat::SimpleSudoku => Line::Row(line.replace('|', " ")),
+            _ => Line::Row(line),
+        }
+    }
+}
+
+/// Key and value of an SDK metadata line, without its `#`. Lines without a
+/// capital letter are comments.
+fn sdk_metadata(line: &str) -> Option<(String, String)> {
+    let mut chars = line.chars();
+    let letter = chars.next().filter(char::is_ascii_uppercase)?;
+    let key = SDK_METADATA
+        .iter()
+        .find(|&&(l, _)| l == letter)
+        .map_or_else(|| letter.to_string(), |&(_, key)| key.to_string());
+    Some((key, chars.as_str().trim().to_string()))
+}
+
+impl<R: BufRead> Iterator for GridStrings<R> {
+    type Item = io::Result<GridText>;
+
+    fn next(&mut self) -> Option<io::Result<GridText>> {
+        let rows = self.input_format.rows(self.grid_lines);
+
+        let mut first_line = 0;
+        let mut grid_string = String::new();
+        // keep the empty lines inside a grid so that errors point to the right line
+        let mut empty_lines = String::new();
+        let mut not_empty_lines = 0;
+
+        while not_empty_lines < rows {
+            let line_content = match self.lines.next() {
+                Some(Ok(line_content)) => line_content,
+                Some(Err(err)) => return Some(Err(err)),
+                // a grid cut short is still returned, to be reported as too short
+                None => break,
+            };
+            self.line += 1;
+
+            let row = match self.classify(line_content) {
+                Line::Row(row) => row,
+                Line::Blank => {
+                    if not_empty_lines > 0 {
+                        empty_lines.push('\n');
+                    }
+                    continue;
+                }
+                Line::Metadata(key, value) => {
+                    self.metadata.push((key, value));
+                    if not_empty_lines > 0 {
+                        empty_lines.push('\n');
+                    }
+                    continue;
+                }
+            };
+
+            if not_empty_lines == 0 {
+                first_line = self.line;
+            } else {
+                grid_string.push_str(&empty_lines);
+                grid_string.push('\n');
+                empty_lines.clear();
+            }
+            grid_string.push_str(&row);
+            not_empty_lines += 1;
+        }
+
+        if not_empty_lines == 0 {
+            return None;
+        }
+        Some(Ok(GridText {
+            line: first_line,
+            text: grid_string,
+            metadata: std::mem::take(&mut self.metadata),
+        }))
+    }
+}
+
+/// Parses a sudoku written in `input_format`.
+pub fn parse_sudoku(text: &str, input_format: FileFormat) -> Result<Sudoku, ParseError> {
+    match input_format {
+        FileFormat::PencilMarks => Sudoku::parse_pencil_marks(text),
+        _ => Sudoku::parse(text),
+    }
+}
+
+/// Splits `text` into the cages of each killer sudoku, with the line they
+/// start at. Blocks of comments only are left out.
+pub fn split_cage_texts(text: &str) -> Vec<(usize, String)> {
+    if text.trim_start().starts_with('{') {
+        let line = text.lines().position(|line| !line.trim().is_empty());
+        return vec![(line.unwrap_or(0) + 1, text.to_string())];
+    }
+
+    let mut blocks: Vec<(usize, String)> = vec![];
+    let mut in_block = false;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            in_block = false;
+            continue;
+        }
+        if !in_block {
+            blocks.push((i + 1, String::new()));
+            in_block = true;
+        }
+        let block = &mut blocks.last_mut().unwrap().1;
+        block.push_str(line);
+        block.push('\n');
+    }
+    blocks.retain(|(_, block)| {
+        block
+            .lines()
+            .any(|line| !line.trim_start().starts_with('#'))
+    });
+    blocks
+}
+
+/// Splits the text of a jigsaw into its cells and the layout of its
+/// regions, its last `cells` characters other than whitespace.
+pub fn split_layout(text: &str, cells: usize) -> (&str, &str) {
+    let start = text
+        .char_indices()
+        .rev()
+        .filter(|(_, c)| !c.is_whitespace())
+        .nth(cells.saturating_sub(1))
+        .map_or(0, |(start, _)| start);
+    text.split_at(start)
+}
+
+/// Line and column, from 1, of the byte at `offset` of `text`.
+pub fn text_position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: &str =
+        "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......";
+
+    fn metadata() -> Vec<(String, String)> {
+        vec![
+            ("author".to_string(), "Arto Inkala".to_string()),
+            ("name".to_string(), "Golden Nugget".to_string()),
+        ]
+    }
+
+    fn read(text: &str, format: FileFormat) -> Vec<(Sudoku, Vec<(String, String)>)> {
+        GridStrings::new(text.as_bytes(), format, 9)
+            .map(|grid| {
+                let grid = grid.unwrap();
+                (parse_sudoku(&grid.text, format).unwrap(), grid.metadata)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn written_formats_read_back() {
+        let sudoku = Sudoku::parse(GRID).unwrap();
+        for format in FileFormat::ALL {
+            let mut out = vec![];
+            write_sudoku(format, &sudoku, &metadata(), &mut out).unwrap();
+            write_sudoku(format, &sudoku, &[], &mut out).unwrap();
+            let text = String::from_utf8(out).unwrap();
+
+            let puzzles = read(&text, format);
+            assert_eq!(puzzles.len(), 2, "{}:\n{}", format, text);
+            for (read, _) in &puzzles {
+                assert_eq!(read.to_line(), GRID, "{}:\n{}", format, text);
+            }
+            assert_eq!(puzzles[0].1, metadata(), "{}:\n{}", format, text);
+            assert!(puzzles[1].1.is_empty(), "{}:\n{}", format, text);
+        }
+    }
+
+    #[test]
+    fn written_formats_are_detected() {
+        let sudoku = Sudoku::parse(GRID).unwrap();
+        for format in FileFormat::ALL {
+            let mut out = vec![];
+            write_sudoku(format, &sudoku, &metadata(), &mut out).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            // .sdm files are read as one-liners, with their 0 as empty cells
+            let expected = match format {
+                FileFormat::Sdm => FileFormat::OneLiner,
+                _ => format,
+            };
+            assert_eq!(FileFormat::detect(&text, 9, true), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn sdk_metadata_lines() {
+        let mut out = vec![];
+        let sudoku = Sudoku::parse(GRID).unwrap();
+        write_sudoku(FileFormat::Sdk, &sudoku, &metadata(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("#AArto Inkala\n4.....8.5 # name=Golden Nugget\n"));
+
+        let text = "#AArto Inkala\n#Bnot a date\n#Qquality\n# a comment\n".to_string()
+            + &sudoku.to_string();
+        let expected = [
+            ("author", "Arto Inkala"),
+            ("date", "not a date"),
+            ("Q", "quality"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(read(&text, FileFormat::Sdk)[0].1, expected);
+    }
+
+    #[test]
+    fn comments_and_metadata() {
+        assert_eq!(
+            comment_metadata("hard one name=Golden Nugget rating=11.9"),
+            [
+                ("comment", "hard one"),
+                ("name", "Golden Nugget"),
+                ("rating", "11.9")
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(
+            metadata_text(&metadata()),
+            "author=Arto Inkala name=Golden Nugget"
+        );
+        assert_eq!(comment_metadata(&metadata_text(&metadata())), metadata());
+        assert_eq!(metadata_comment(&[]), "");
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for format in FileFormat::ALL {
+            assert_eq!(format.to_string().parse::<FileFormat>(), Ok(format));
+        }
+        assert!("sdx".parse::<FileFormat>().is_err());
+        assert_eq!(
+            FileFormat::from_extension(Path::new("puzzles.SDK")),
+            Some(FileFormat::Sdk)
+        );
+        assert_eq!(FileFormat::from_extension(Path::new("puzzles.txt")), None);
+    }
+
+    #[test]
+    fn grid_lines_and_errors() {
+        // the empty line inside the grid keeps the line of the error
+        let text = "# comment\n\n123\n\n45x\n\n789\n";
+        let grids = GridStrings::new(text.as_bytes(), FileFormat::MultiLine, 3)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(grids.len(), 1);
+        assert_eq!(grids[0].line, 3);
+        let err = Sudoku::parse(&grids[0].text).unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+
+        assert_eq!(text_position("ab\ncd", 4), (2, 2));
+        assert_eq!(split_layout("1 2 3 4 aabb", 4), ("1 2 3 4 ", "aabb"));
+    }
+
+    #[test]
+    fn cage_texts() {
+        let text = "# killers\n\n1 2 r1c1 r1c2\n3 r2c1\n\n# only a comment\n\nr1c1 5\n";
+        assert_eq!(
+            split_cage_texts(text),
+            [
+                (3, "1 2 r1c1 r1c2\n3 r2c1\n".to_string()),
+                (8, "r1c1 5\n".to_string())
+            ]
+        );
+        assert_eq!(
+            split_cage_texts("\n{\"cages\": []}"),
+            [(2, "\n{\"cages\": []}".to_string())]
+        );
+    }
+}
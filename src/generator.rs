@@ -0,0 +1,244 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::grid::Grid;
+use crate::solver::{
+    build_possible_values_grid, count_solutions, fill_hidden_singles, least_possibilities_cell,
+    set_cell_value_at,
+};
+use crate::sudoku::Sudoku;
+
+/// Pattern the clues of a generated puzzle follow.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// Invariant under a half-turn around the center cell.
+    Rotational,
+    /// Invariant under a reflection along the main diagonal.
+    Diagonal,
+    /// Invariant under a left-right reflection.
+    Mirror,
+}
+
+impl Symmetry {
+    /// Cells that must be cleared together with the cell at `index`.
+    fn orbit(self, index: usize) -> Vec<usize> {
+        let (row, column) = (index / 9, index % 9);
+        let mirror = match self {
+            Symmetry::None => index,
+            Symmetry::Rotational => 80 - index,
+            Symmetry::Diagonal => column * 9 + row,
+            Symmetry::Mirror => row * 9 + 8 - column,
+        };
+        if mirror == index {
+            vec![index]
+        } else {
+            vec![index, mirror]
+        }
+    }
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Symmetry, String> {
+        match s {
+            "none" => Ok(Symmetry::None),
+            "rotational" => Ok(Symmetry::Rotational),
+            "diagonal" => Ok(Symmetry::Diagonal),
+            "mirror" => Ok(Symmetry::Mirror),
+            _ => Err(format!(
+                "unknown symmetry '{}', expected none, rotational, diagonal or mirror",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Symmetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Symmetry::None => "none",
+            Symmetry::Rotational => "rotational",
+            Symmetry::Diagonal => "diagonal",
+            Symmetry::Mirror => "mirror",
+        })
+    }
+}
+
+/// Parameters of [`generate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GeneratorOptions {
+    /// Seed of the random generator: the same seed and options always give
+    /// the same puzzle.
+    pub seed: u64,
+    /// Clues are no longer removed once the puzzle is down to this count.
+    pub min_clues: usize,
+    pub symmetry: Symmetry,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        GeneratorOptions {
+            seed: 0,
+            min_clues: 17,
+            symmetry: Symmetry::None,
+        }
+    }
+}
+
+/// Generates a puzzle with a unique solution.
+///
+/// A random full grid is built first, then clues are removed in random
+/// order, following `options.symmetry`, as long as the solution stays unique
+/// and the clue count does not go under `options.min_clues`.
+pub fn generate(options: &GeneratorOptions) -> Sudoku {
+    let mut rng = ChaCha8Rng::seed_from_u64(options.seed);
+
+    let mut puzzle = random_full_grid(&mut rng);
+    let mut clues = 81;
+
+    let mut orbits = (0..81)
+        .map(|index| options.symmetry.orbit(index))
+        .filter(|orbit| orbit.iter().all(|&i| i >= orbit[0]))
+        .collect::<Vec<_>>();
+    orbits.shuffle(&mut rng);
+
+    for orbit in orbits {
+        if clues < options.min_clues + orbit.len() {
+            continue;
+        }
+
+        let mut candidate = puzzle;
+        for &index in &orbit {
+            candidate.clear(index);
+        }
+
+        if count_solutions(candidate, 2).count == 1 {
+            puzzle = candidate;
+            clues -= orbit.len();
+        }
+    }
+
+    Sudoku::from_grid(puzzle)
+}
+
+/// Builds a random complete grid from `seed`.
+pub fn generate_full_grid(seed: u64) -> Sudoku {
+    Sudoku::from_grid(random_full_grid(&mut ChaCha8Rng::seed_from_u64(seed)))
+}
+
+fn random_full_grid(rng: &mut ChaCha8Rng) -> Grid {
+    let mut grid = Grid::empty();
+    build_possible_values_grid(&mut grid);
+
+    // an empty grid always has a solution
+    fill_grid_recurse(grid, rng).unwrap()
+}
+
+fn fill_grid_recurse(mut grid: Grid, rng: &mut ChaCha8Rng) -> Option<Grid> {
+    if !fill_hidden_singles(&mut grid) {
+        return None;
+    }
+
+    match least_possibilities_cell(&grid) {
+        Some((index, poss)) => {
+            let mut values = poss.iter().collect::<Vec<_>>();
+            values.shuffle(rng);
+
+            for cell_value in values {
+                let mut new_g = grid;
+                if set_cell_value_at(&mut new_g, index, cell_value) {
+                    if let Some(full) = fill_grid_recurse(new_g, rng) {
+                        return Some(full);
+                    }
+                }
+            }
+            None
+        }
+        None => Some(grid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(seed: u64, min_clues: usize, symmetry: Symmetry) -> GeneratorOptions {
+        GeneratorOptions {
+            seed,
+            min_clues,
+            symmetry,
+        }
+    }
+
+    #[test]
+    fn puzzles_are_unique_and_from_the_full_grid() {
+        for seed in 0..5 {
+            let puzzle = generate(&options(seed, 17, Symmetry::None));
+            assert_eq!(count_solutions(*puzzle.grid(), 2).count, 1, "{}", seed);
+            let full = generate_full_grid(seed);
+            for index in 0..81 {
+                if let Some(value) = puzzle.grid().value(index) {
+                    assert_eq!(full.grid().value(index), Some(value));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clues_follow_the_symmetry() {
+        let symmetries = [Symmetry::Rotational, Symmetry::Diagonal, Symmetry::Mirror];
+        for symmetry in symmetries {
+            for seed in 0..3 {
+                let puzzle = generate(&options(seed, 17, symmetry));
+                let grid = puzzle.grid();
+                assert_eq!(count_solutions(*grid, 2).count, 1);
+                for index in 0..81 {
+                    for &other in &symmetry.orbit(index) {
+                        assert_eq!(
+                            grid.is_value(index),
+                            grid.is_value(other),
+                            "{} {}",
+                            symmetry,
+                            puzzle.to_line()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clues_stay_above_the_minimum() {
+        for min_clues in [25, 30, 40] {
+            for symmetry in [Symmetry::None, Symmetry::Rotational] {
+                let puzzle = generate(&options(7, min_clues, symmetry));
+                let clues = puzzle.grid().value_count();
+                assert!(clues >= min_clues, "{} clues", clues);
+            }
+        }
+        // a floor of every cell keeps the full grid
+        assert_eq!(
+            generate(&options(7, 81, Symmetry::None)).to_line(),
+            generate_full_grid(7).to_line()
+        );
+    }
+
+    #[test]
+    fn same_seed_same_puzzle() {
+        let first = generate(&options(42, 20, Symmetry::Mirror));
+        let second = generate(&options(42, 20, Symmetry::Mirror));
+        assert_eq!(first.to_line(), second.to_line());
+        assert_eq!(first.to_string(), second.to_string());
+        assert_ne!(
+            first.to_line(),
+            generate(&options(43, 20, Symmetry::Mirror)).to_line()
+        );
+    }
+}
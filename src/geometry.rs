@@ -0,0 +1,558 @@
+//! Houses and peers derived from the layout of a grid. The classic 9x9
+//! tables are computed at compile time by `const fn`s, a [`Geometry`] is
+//! built for any box shape, with extra regions such as diagonals, or for the
+//! irregular regions of jigsaw sudokus, and can hold the cages of a killer
+//! sudoku.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::board::{BoxShape, ValueSet, MAX_SIZE};
+use crate::grid::House;
+use crate::killer::{check_cages, combinations, Cage, CageError};
+
+/// Cells of the 27 houses of the classic grid: the rows, then the columns,
+/// then the boxes.
+pub(crate) const HOUSES: [[usize; 9]; 27] = classic_houses();
+
+/// For each cell of the classic grid, the other cells of its row, column and
+/// box.
+pub(crate) const HOUSE_PEERS: [[[usize; 8]; 3]; 81] = classic_house_peers();
+
+/// For each cell of the classic grid, the 20 cells sharing a house with it,
+/// in increasing order.
+pub(crate) const PEERS: [[usize; 20]; 81] = classic_peers();
+
+const fn classic_houses() -> [[usize; 9]; 27] {
+    let mut houses = [[0; 9]; 27];
+    let mut i = 0;
+    while i < 9 {
+        let mut k = 0;
+        while k < 9 {
+            houses[i][k] = i * 9 + k;
+            houses[9 + i][k] = k * 9 + i;
+            houses[18 + i][k] = (i / 3) * 27 + (i % 3) * 3 + (k / 3) * 9 + k % 3;
+            k += 1;
+        }
+        i += 1;
+    }
+    houses
+}
+
+/// Indexes in [`HOUSES`] of the row, column and box of a cell.
+pub(crate) const fn houses_of(index: usize) -> [usize; 3] {
+    let (row, column) = (index / 9, index % 9);
+    [row, 9 + column, 18 + box_of(index)]
+}
+
+pub(crate) const fn box_of(index: usize) -> usize {
+    (index / 27) * 3 + (index % 9) / 3
+}
+
+const fn classic_house_peers() -> [[[usize; 8]; 3]; 81] {
+    let mut peers = [[[0; 8]; 3]; 81];
+    let mut index = 0;
+    while index < 81 {
+        let houses = houses_of(index);
+        let mut h = 0;
+        while h < 3 {
+            let (mut k, mut n) = (0, 0);
+            while k < 9 {
+                let cell = HOUSES[houses[h]][k];
+                if cell != index {
+                    peers[index][h][n] = cell;
+                    n += 1;
+                }
+                k += 1;
+            }
+            h += 1;
+        }
+        index += 1;
+    }
+    peers
+}
+
+const fn classic_peers() -> [[usize; 20]; 81] {
+    let mut peers = [[0; 20]; 81];
+    let mut index = 0;
+    while index < 81 {
+        let (mut cell, mut n) = (0, 0);
+        while cell < 81 {
+            let shares_house =
+                cell / 9 == index / 9 || cell % 9 == index % 9 || box_of(cell) == box_of(index);
+            if cell != index && shares_house {
+                peers[index][n] = cell;
+                n += 1;
+            }
+            cell += 1;
+        }
+        index += 1;
+    }
+    peers
+}
+
+/// The houses of a grid and the peers of each cell: the rows, the columns,
+/// the boxes of a box shape or the regions of a jigsaw, plus optional extra
+/// regions and killer cages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    size: usize,
+    /// Shape of the boxes, `None` for jigsaws.
+    shape: Option<BoxShape>,
+    /// Cells of each house: the rows, the columns, the boxes, then the
+    /// regions.
+    houses: Vec<Vec<usize>>,
+    /// Indexes in `houses` of the houses of each cell.
+    cell_houses: Vec<Vec<usize>>,
+    /// Cells sharing a house, or a cage without repeated values, with each
+    /// cell, in increasing order.
+    peers: Vec<Vec<usize>>,
+    cages: Vec<Cage>,
+    /// Index in `cages` of the cage of each cell.
+    cell_cages: Vec<Option<usize>>,
+    /// Sets of values adding up to the sum of each cage without repeated
+    /// values, empty for the others.
+    cage_combinations: Vec<Vec<ValueSet>>,
+}
+
+impl Geometry {
+    /// The rows, columns and boxes of the classic grid, built once.
+    pub fn classic() -> &'static Geometry {
+        static CLASSIC: OnceLock<Geometry> = OnceLock::new();
+        CLASSIC.get_or_init(|| Geometry::new(BoxShape::CLASSIC))
+    }
+
+    /// The rows, columns and boxes of `shape`.
+    pub fn new(shape: BoxShape) -> Geometry {
+        Geometry::with_regions(shape, vec![])
+    }
+
+    /// The rows, columns and boxes of `shape`, and `regions` that must also
+    /// hold every value once, such as the diagonals.
+    ///
+    /// # Panics
+    ///
+    /// When a region does not have as many cells as there are values, or
+    /// has a cell outside of the grid.
+    pub fn with_regions(shape: BoxShape, regions: Vec<Vec<usize>>) -> Geometry {
+        let size = shape.size();
+        let mut houses: Vec<Vec<usize>> = Vec::with_capacity(3 * size + regions.len());
+        for row in 0..size {
+            houses.push((0..size).map(|column| row * size + column).collect());
+        }
+        for column in 0..size {
+            houses.push((0..size).map(|row| row * size + column).collect());
+        }
+        for b in 0..size {
+            // there are `shape.rows` boxes across and `shape.columns` down
+            let (top, left) = (
+                (b / shape.rows) * shape.rows,
+                (b % shape.rows) * shape.columns,
+            );
+            houses.push(
+                (0..size)
+                    .map(|i| (top + i / shape.columns) * size + left + i % shape.columns)
+                    .collect(),
+            );
+        }
+        push_regions(&mut houses, size, regions);
+        Geometry::from_houses(size, Some(shape), houses)
+    }
+
+    /// This geometry with `regions` added, such as a jigsaw with its
+    /// diagonals.
+    ///
+    /// # Panics
+    ///
+    /// As [`Geometry::with_regions`].
+    pub fn extended(self, regions: Vec<Vec<usize>>) -> Geometry {
+        let mut houses = self.houses;
+        push_regions(&mut houses, self.size, regions);
+        let mut geometry = Geometry::from_houses(self.size, self.shape, houses);
+        geometry.add_cages(self.cages);
+        geometry
+    }
+
+    /// This geometry with the `cages` of a killer sudoku, which must cover
+    /// every cell once and be able to reach their sums, as checked by
+    /// [`check_cages`]. The cells of a cage without repeated values become
+    /// peers.
+    pub fn with_cages(mut self, cages: Vec<Cage>) -> Result<Geometry, CageError> {
+        check_cages(&cages, self.size)?;
+        self.add_cages(cages);
+        Ok(self)
+    }
+
+    fn add_cages(&mut self, cages: Vec<Cage>) {
+        for (number, cage) in cages.iter().enumerate() {
+            for &cell in &cage.cells {
+                self.cell_cages[cell] = Some(number);
+                if cage.no_repeat {
+                    let peers = &mut self.peers[cell];
+                    peers.extend(cage.cells.iter().filter(|&&other| other != cell));
+                    peers.sort_unstable();
+                    peers.dedup();
+                }
+            }
+            self.cage_combinations.push(if cage.no_repeat {
+                combinations(cage.sum, cage.cells.len(), self.size)
+            } else {
+                vec![]
+            });
+        }
+        self.cages = cages;
+    }
+
+    /// The rows and columns of a jigsaw sudoku, whose boxes are irregular
+    /// regions. `layout` names the region of each cell with a character,
+    /// `111222333` starting the first row of a grid whose first three
+    /// regions begin side by side. Whitespace is ignored. The regions must
+    /// be as many as the rows, of as many cells, and each in one piece.
+    pub fn jigsaw(layout: &str) -> Result<Geometry, RegionError> {
+        let cells = layout
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<Vec<_>>();
+        let size = (1..=MAX_SIZE)
+            .find(|size| size * size == cells.len())
+            .ok_or(RegionError::CellCount(cells.len()))?;
+
+        // regions are numbered in the order they first appear
+        let mut names = vec![];
+        let mut regions: Vec<Vec<usize>> = vec![];
+        for (index, &name) in cells.iter().enumerate() {
+            match names.iter().position(|&n| n == name) {
+                Some(region) => regions[region].push(index),
+                None => {
+                    names.push(name);
+                    regions.push(vec![index]);
+                }
+            }
+        }
+        if regions.len() != size {
+            return Err(RegionError::RegionCount(regions.len(), size));
+        }
+        for (&name, region) in names.iter().zip(&regions) {
+            if region.len() != size {
+                return Err(RegionError::RegionSize(name, region.len(), size));
+            }
+            if !is_connected(region, size) {
+                return Err(RegionError::Disconnected(name));
+            }
+        }
+
+        let mut houses: Vec<Vec<usize>> = Vec::with_capacity(3 * size);
+        for row in 0..size {
+            houses.push((0..size).map(|column| row * size + column).collect());
+        }
+        for column in 0..size {
+            houses.push((0..size).map(|row| row * size + column).collect());
+        }
+        houses.extend(regions);
+        Ok(Geometry::from_houses(size, None, houses))
+    }
+
+    fn from_houses(size: usize, shape: Option<BoxShape>, houses: Vec<Vec<usize>>) -> Geometry {
+        let mut cell_houses = vec![vec![]; size * size];
+        for (house, cells) in houses.iter().enumerate() {
+            for &cell in cells {
+                cell_houses[cell].push(house);
+            }
+        }
+
+        let peers = cell_houses
+            .iter()
+            .enumerate()
+            .map(|(index, of_cell)| {
+                let mut peers = of_cell
+                    .iter()
+                    .flat_map(|&house| houses[house].iter().copied())
+                    .filter(|&cell| cell != index)
+                    .collect::<Vec<_>>();
+                peers.sort_unstable();
+                peers.dedup();
+                peers
+            })
+            .collect();
+
+        Geometry {
+            size,
+            shape,
+            houses,
+            cell_houses,
+            peers,
+            cages: vec![],
+            cell_cages: vec![None; size * size],
+            cage_combinations: vec![],
+        }
+    }
+
+    /// Shape of the boxes, `None` for jigsaws.
+    pub fn shape(&self) -> Option<BoxShape> {
+        self.shape
+    }
+
+    /// Number of rows, columns and values.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cell_houses.len()
+    }
+
+    /// Cells of every house: the rows, the columns, the boxes, then the
+    /// extra regions.
+    pub fn houses(&self) -> &[Vec<usize>] {
+        &self.houses
+    }
+
+    /// The house at `index` of [`Geometry::houses`]. The regions of a
+    /// jigsaw come right after the columns.
+    pub fn house(&self, index: usize) -> House {
+        let size = self.size();
+        match index / size {
+            0 => House::Row(index),
+            1 => House::Column(index - size),
+            2 if self.shape.is_some() => House::Box(index - 2 * size),
+            _ if self.shape.is_some() => House::Region(index - 3 * size),
+            _ => House::Region(index - 2 * size),
+        }
+    }
+
+    /// Indexes in [`Geometry::houses`] of the houses of the cell at `index`.
+    pub fn houses_of(&self, index: usize) -> &[usize] {
+        &self.cell_houses[index]
+    }
+
+    /// Cells sharing a house, or a cage without repeated values, with the
+    /// cell at `index`, in increasing order.
+    pub fn peers(&self, index: usize) -> &[usize] {
+        &self.peers[index]
+    }
+
+    /// The killer cages, empty for other sudokus.
+    pub fn cages(&self) -> &[Cage] {
+        &self.cages
+    }
+
+    /// Index in [`Geometry::cages`] of the cage of the cell at `index`.
+    pub fn cage_of(&self, index: usize) -> Option<usize> {
+        self.cell_cages[index]
+    }
+
+    /// Sets of values adding up to the sum of the cage at `index` of
+    /// [`Geometry::cages`], empty when its values may repeat.
+    pub fn cage_combinations(&self, index: usize) -> &[ValueSet] {
+        &self.cage_combinations[index]
+    }
+}
+
+fn push_regions(houses: &mut Vec<Vec<usize>>, size: usize, regions: Vec<Vec<usize>>) {
+    for region in regions {
+        assert!(
+            region.len() == size && region.iter().all(|&cell| cell < size * size),
+            "a region holds {} cells of the grid",
+            size
+        );
+        houses.push(region);
+    }
+}
+
+/// The two main diagonals of a grid of `size` rows, the extra regions of
+/// Sudoku-X: from the top left corner, then from the top right one.
+pub fn diagonals(size: usize) -> Vec<Vec<usize>> {
+    vec![
+        (0..size).map(|i| i * (size + 1)).collect(),
+        (0..size).map(|i| (i + 1) * (size - 1)).collect(),
+    ]
+}
+
+/// The windows of Hyper sudokus (Windoku): boxes of `shape` set one cell
+/// apart from each other and from the top and left edges, as many as fit in
+/// the grid. The classic grid has four, starting at the second and sixth
+/// rows and columns.
+pub fn windows(shape: BoxShape) -> Vec<Vec<usize>> {
+    let size = shape.size();
+    let mut windows = vec![];
+    for down in 0..(size - 1) / (shape.rows + 1) {
+        for across in 0..(size - 1) / (shape.columns + 1) {
+            let (top, left) = (
+                1 + down * (shape.rows + 1),
+                1 + across * (shape.columns + 1),
+            );
+            windows.push(
+                (0..size)
+                    .map(|i| (top + i / shape.columns) * size + left + i % shape.columns)
+                    .collect(),
+            );
+        }
+    }
+    windows
+}
+
+/// Returns true when the cells of `region`, in a grid of `size` columns,
+/// can all be reached from one another through the sides of the cells.
+fn is_connected(region: &[usize], size: usize) -> bool {
+    let mut reached = vec![region[0]];
+    let mut next = 0;
+    while next < reached.len() {
+        let cell = reached[next];
+        next += 1;
+        for &other in region {
+            let (row, column) = (cell / size, cell % size);
+            let (other_row, other_column) = (other / size, other % size);
+            let adjacent = row.abs_diff(other_row) + column.abs_diff(other_column) == 1;
+            if adjacent && !reached.contains(&other) {
+                reached.push(other);
+            }
+        }
+    }
+    reached.len() == region.len()
+}
+
+/// Why a jigsaw layout was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegionError {
+    /// Not as many cells as a square grid has, with the number found.
+    CellCount(usize),
+    /// Not as many regions as rows, with the numbers found and expected.
+    RegionCount(usize, usize),
+    /// A region, with its name, without as many cells as a row.
+    RegionSize(char, usize, usize),
+    /// A region, with its name, in several pieces.
+    Disconnected(char),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegionError::CellCount(n) => write!(f, "{} cells do not make a square grid", n),
+            RegionError::RegionCount(found, expected) => {
+                write!(f, "{} regions, expected {}", found, expected)
+            }
+            RegionError::RegionSize(name, found, expected) => write!(
+                f,
+                "region {:?} has {} cells, expected {}",
+                name, found, expected
+            ),
+            RegionError::Disconnected(name) => write!(f, "region {:?} is not in one piece", name),
+        }
+    }
+}
+
+impl Error for RegionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{ADJACENT_CELLS, ADJACENT_VALUES};
+    use crate::grid::{check_grid_at, parse_grid};
+
+    #[test]
+    fn classic_tables_match_the_written_ones() {
+        assert_eq!(HOUSE_PEERS, ADJACENT_CELLS);
+        assert_eq!(PEERS, ADJACENT_VALUES);
+    }
+
+    #[test]
+    fn classic_geometry_matches_the_written_tables() {
+        let geometry = Geometry::new(BoxShape::CLASSIC);
+        assert_eq!(geometry.houses().len(), 27);
+        for (house, cells) in geometry.houses().iter().enumerate() {
+            assert_eq!(cells[..], HOUSES[house]);
+        }
+        for index in 0..81 {
+            assert_eq!(geometry.peers(index), ADJACENT_VALUES[index]);
+            assert_eq!(geometry.houses_of(index), houses_of(index));
+            for (h, &house) in geometry.houses_of(index).iter().enumerate() {
+                let others = geometry.houses()[house]
+                    .iter()
+                    .copied()
+                    .filter(|&cell| cell != index)
+                    .collect::<Vec<_>>();
+                assert_eq!(others, ADJACENT_CELLS[index][h]);
+            }
+        }
+    }
+
+    #[test]
+    fn regions_add_houses_and_peers() {
+        let diagonal = (0..9).map(|i| i * 10).collect::<Vec<_>>();
+        let geometry = Geometry::with_regions(BoxShape::CLASSIC, vec![diagonal.clone()]);
+        assert_eq!(geometry.houses()[27], diagonal);
+        assert_eq!(geometry.house(27), House::Region(0));
+        assert_eq!(geometry.houses_of(40), [4, 13, 22, 27]);
+        assert!(geometry.peers(0).contains(&80));
+        assert!(!geometry.peers(1).contains(&80));
+    }
+
+    #[test]
+    fn diagonals_and_windows() {
+        assert_eq!(
+            diagonals(9),
+            [
+                [0, 10, 20, 30, 40, 50, 60, 70, 80],
+                [8, 16, 24, 32, 40, 48, 56, 64, 72]
+            ]
+        );
+        let classic = windows(BoxShape::CLASSIC);
+        assert_eq!(classic.len(), 4);
+        assert_eq!(classic[0], [10, 11, 12, 19, 20, 21, 28, 29, 30]);
+        assert_eq!(classic[3], [50, 51, 52, 59, 60, 61, 68, 69, 70]);
+        let geometry = Geometry::jigsaw(&"123456789".repeat(9))
+            .unwrap()
+            .extended(diagonals(9));
+        assert_eq!(geometry.houses().len(), 29);
+        assert_eq!(geometry.house(28), House::Region(10));
+    }
+
+    #[test]
+    fn windows_of_rectangular_boxes() {
+        // a single window of 2 rows and 3 columns in the 6x6 grid
+        let shape = BoxShape {
+            rows: 2,
+            columns: 3,
+        };
+        assert_eq!(windows(shape), [[7, 8, 9, 13, 14, 15]]);
+        // two windows of 2 rows and 4 columns down the 8x8 grid
+        let shape = BoxShape {
+            rows: 2,
+            columns: 4,
+        };
+        assert_eq!(
+            windows(shape),
+            [
+                [9, 10, 11, 12, 17, 18, 19, 20],
+                [33, 34, 35, 36, 41, 42, 43, 44]
+            ]
+        );
+        for shape in [
+            BoxShape::CLASSIC,
+            shape,
+            BoxShape {
+                rows: 4,
+                columns: 2,
+            },
+            BoxShape {
+                rows: 3,
+                columns: 4,
+            },
+        ] {
+            let size = shape.size();
+            // the geometry checks that each window fits in the grid
+            let geometry = Geometry::with_regions(shape, windows(shape));
+            assert!(geometry.houses().len() > 3 * size);
+        }
+    }
+
+    #[test]
+    fn grid_check_includes_the_extra_regions() {
+        // r1c1 and r5c5 share no row, column or box, only a diagonal
+        let grid = parse_grid(&format!("1{}1{}", ".".repeat(39), ".".repeat(40))).unwrap();
+        let diagonal = Geometry::with_regions(BoxShape::CLASSIC, diagonals(9));
+        assert!(check_grid_at(grid, Geometry::classic(), 40));
+        assert!(!check_grid_at(grid, &diagonal, 40));
+    }
+}
@@ -0,0 +1,769 @@
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+
+use crate::geometry::{box_of, houses_of, Geometry, RegionError, HOUSES, HOUSE_PEERS, PEERS};
+
+/// Set of values, stored as one bit per value.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Candidates(u16);
+
+impl Candidates {
+    pub const NONE: Candidates = Candidates(0);
+    pub const ALL: Candidates = Candidates(0x1ff);
+
+    pub fn from_bits(bits: u16) -> Candidates {
+        Candidates(bits & Candidates::ALL.0)
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// The set holding only `value`.
+    pub fn only(value: usize) -> Candidates {
+        Candidates(1 << value)
+    }
+
+    pub fn contains(self, value: usize) -> bool {
+        self.0 & (1 << value) != 0
+    }
+
+    pub fn insert(&mut self, value: usize) {
+        self.0 |= 1 << value;
+    }
+
+    pub fn remove(&mut self, value: usize) {
+        self.0 &= !(1 << value);
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The value of the set when it holds exactly one.
+    pub fn single(self) -> Option<usize> {
+        if self.0 != 0 && self.0 & (self.0 - 1) == 0 {
+            Some(self.0.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The values of the set, in increasing order.
+    pub fn iter(self) -> CandidatesIter {
+        CandidatesIter(self.0)
+    }
+}
+
+impl fmt::Debug for Candidates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(|v| v + 1)).finish()
+    }
+}
+
+impl BitOr for Candidates {
+    type Output = Candidates;
+
+    fn bitor(self, rhs: Candidates) -> Candidates {
+        Candidates(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Candidates {
+    fn bitor_assign(&mut self, rhs: Candidates) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Candidates {
+    type Output = Candidates;
+
+    fn bitand(self, rhs: Candidates) -> Candidates {
+        Candidates(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for Candidates {
+    fn bitand_assign(&mut self, rhs: Candidates) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl Not for Candidates {
+    type Output = Candidates;
+
+    fn not(self) -> Candidates {
+        Candidates(!self.0 & Candidates::ALL.0)
+    }
+}
+
+impl FromIterator<usize> for Candidates {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Candidates {
+        let mut candidates = Candidates::NONE;
+        for value in iter {
+            candidates.insert(value);
+        }
+        candidates
+    }
+}
+
+impl IntoIterator for Candidates {
+    type Item = usize;
+    type IntoIter = CandidatesIter;
+
+    fn into_iter(self) -> CandidatesIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the values of [`Candidates`].
+#[derive(Clone, Debug)]
+pub struct CandidatesIter(u16);
+
+impl Iterator for CandidatesIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let value = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(value)
+    }
+}
+
+/// State of a single cell: either a placed value (0-based) or the values
+/// still possible for it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CellValue {
+    Value(usize),
+    Possibilities(Candidates),
+}
+
+impl CellValue {
+    pub fn is_value(&self) -> bool {
+        match *self {
+            CellValue::Value(_) => true,
+            CellValue::Possibilities(_) => false,
+        }
+    }
+
+    pub fn get_nb_possibility(&self) -> usize {
+        match *self {
+            CellValue::Value(_) => 10,
+            CellValue::Possibilities(values) => values.len(),
+        }
+    }
+}
+
+/// Name of a cell in the `r3c5` notation, rows and columns counted from 1.
+pub(crate) fn cell_name(index: usize) -> String {
+    cell_name_in(index, 9)
+}
+
+/// Name of a cell of a grid with `size` columns.
+pub(crate) fn cell_name_in(index: usize, size: usize) -> String {
+    format!("r{}c{}", index / size + 1, index % size + 1)
+}
+
+/// A row, column or box, numbered from 0. Boards can also have extra
+/// regions, such as diagonals, and killer cages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum House {
+    Row(usize),
+    Column(usize),
+    Box(usize),
+    Region(usize),
+    Cage(usize),
+}
+
+impl House {
+    /// The 27 houses, boxes first as they are where people look first.
+    pub fn all() -> impl Iterator<Item = House> {
+        (0..9)
+            .map(House::Box)
+            .chain((0..9).map(House::Row))
+            .chain((0..9).map(House::Column))
+    }
+
+    /// The cells of a house of the classic grid.
+    ///
+    /// # Panics
+    ///
+    /// For regions and cages, which the classic grid does not have.
+    pub fn cells(self) -> [usize; 9] {
+        HOUSES[self.index()]
+    }
+
+    /// Index of the house in [`HOUSES`].
+    pub(crate) fn index(self) -> usize {
+        match self {
+            House::Row(r) => r,
+            House::Column(c) => 9 + c,
+            House::Box(b) => 18 + b,
+            House::Region(_) | House::Cage(_) => {
+                panic!("the classic grid has no extra region or cage")
+            }
+        }
+    }
+}
+
+impl fmt::Display for House {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            House::Row(r) => write!(f, "row {}", r + 1),
+            House::Column(c) => write!(f, "column {}", c + 1),
+            House::Box(b) => write!(f, "box {}", b + 1),
+            House::Region(r) => write!(f, "region {}", r + 1),
+            House::Cage(c) => write!(f, "cage {}", c + 1),
+        }
+    }
+}
+
+/// The 81 cells of a grid, row by row.
+///
+/// Each empty cell keeps the bitmask of its possible values, and each row,
+/// column and box keeps the bitmask of the values already placed in it.
+/// Placing a value removes it from the possibilities of the cells sharing a
+/// house with it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Grid {
+    /// Placed value + 1, 0 for an empty cell.
+    values: [u8; 81],
+    possibilities: [Candidates; 81],
+    placed: [Candidates; 27],
+}
+
+impl Default for Grid {
+    fn default() -> Grid {
+        Grid::empty()
+    }
+}
+
+impl Grid {
+    /// A grid without any value, every value being possible everywhere.
+    pub fn empty() -> Grid {
+        Grid {
+            values: [0; 81],
+            possibilities: [Candidates::ALL; 81],
+            placed: [Candidates::NONE; 27],
+        }
+    }
+
+    pub fn cell(&self, index: usize) -> CellValue {
+        match self.value(index) {
+            Some(value) => CellValue::Value(value),
+            None => CellValue::Possibilities(self.possibilities[index]),
+        }
+    }
+
+    /// The cells, row by row.
+    pub fn cells(&self) -> impl Iterator<Item = CellValue> + '_ {
+        (0..81).map(move |index| self.cell(index))
+    }
+
+    pub fn value(&self, index: usize) -> Option<usize> {
+        match self.values[index] {
+            0 => None,
+            v => Some(usize::from(v) - 1),
+        }
+    }
+
+    pub fn is_value(&self, index: usize) -> bool {
+        self.values[index] != 0
+    }
+
+    /// Number of cells holding a value.
+    pub fn value_count(&self) -> usize {
+        self.values.iter().filter(|&&value| value != 0).count()
+    }
+
+    /// Possible values of an empty cell, none for a cell holding a value.
+    pub fn candidates(&self, index: usize) -> Candidates {
+        self.possibilities[index]
+    }
+
+    /// Overrides the possible values of an empty cell.
+    pub fn set_candidates(&mut self, index: usize, candidates: Candidates) {
+        if !self.is_value(index) {
+            self.possibilities[index] = candidates;
+        }
+    }
+
+    /// Removes a possible value from an empty cell, returning true when it
+    /// was possible.
+    pub fn remove_candidate(&mut self, index: usize, value: usize) -> bool {
+        let was_possible = self.possibilities[index].contains(value);
+        self.possibilities[index].remove(value);
+        was_possible
+    }
+
+    /// Values placed in a house, see [`HOUSES`].
+    pub(crate) fn placed(&self, house: usize) -> Candidates {
+        self.placed[house]
+    }
+
+    /// Values that no cell sharing a house with `index` holds.
+    pub fn allowed(&self, index: usize) -> Candidates {
+        let [row, column, block] = houses_of(index);
+        !(self.placed[row] | self.placed[column] | self.placed[block])
+    }
+
+    /// Places `value` at `index` and removes it from the possibilities of
+    /// the cells sharing a house with it.
+    pub fn place(&mut self, index: usize, value: usize) {
+        if let Some(previous) = self.value(index) {
+            self.unplace(index, previous);
+        }
+
+        self.values[index] = value as u8 + 1;
+        self.possibilities[index] = Candidates::NONE;
+        for &house in &houses_of(index) {
+            self.placed[house].insert(value);
+        }
+        for &peer in &PEERS[index] {
+            self.possibilities[peer].remove(value);
+        }
+    }
+
+    /// Empties the cell at `index`. Its possibilities, and the ones of the
+    /// cells sharing a house with it, are recomputed from the placed values.
+    pub fn clear(&mut self, index: usize) {
+        if let Some(value) = self.value(index) {
+            self.unplace(index, value);
+            self.possibilities[index] = self.allowed(index);
+            for &peer in &PEERS[index] {
+                if !self.is_value(peer) && self.allowed(peer).contains(value) {
+                    self.possibilities[peer].insert(value);
+                }
+            }
+        }
+    }
+
+    fn unplace(&mut self, index: usize, value: usize) {
+        self.values[index] = 0;
+        for &house in &houses_of(index) {
+            // a duplicated value stays placed in the house
+            let still_placed = HOUSES[house]
+                .iter()
+                .any(|&cell| cell != index && self.value(cell) == Some(value));
+            if !still_placed {
+                self.placed[house].remove(value);
+            }
+        }
+    }
+}
+
+/// Returns true when every cell holds a value and no house contains a
+/// duplicate.
+pub fn is_grid_complete_full(g: Grid) -> bool {
+    let geometry = Geometry::classic();
+    (0..81).all(|index| g.is_value(index) && check_grid_at(g, geometry, index))
+}
+
+/// Formats a grid over nine lines, with a blank line between bands.
+///
+/// Empty cells are printed as `_`, or as their remaining possibilities
+/// between parentheses when `with_possibilities` is set.
+pub fn format_grid(g: &Grid, with_possibilities: bool) -> String {
+    let mut cnt = 0;
+    let mut line = 0;
+
+    let mut output = String::new();
+
+    for x in g.cells() {
+        cnt += 1;
+
+        match x {
+            CellValue::Value(i) => output.push_str(&(i + 1).to_string()),
+            CellValue::Possibilities(p) => {
+                if with_possibilities {
+                    output.push('(');
+                    for val in p {
+                        output.push_str(&(val + 1).to_string());
+                    }
+                    output.push(')');
+                } else {
+                    output.push('_');
+                }
+            }
+        }
+
+        if cnt == 9 {
+            line += 1;
+            output.push('\n');
+            cnt = 0;
+            if line == 3 {
+                line = 0;
+                output.push('\n');
+            }
+        } else if cnt % 3 == 0 {
+            output.push_str("   ");
+        } else {
+            output.push(' ');
+        }
+    }
+    output
+}
+
+/// Checks that the houses of `geometry` holding the cell at `index` have no
+/// duplicate value: its row, column and box, and the extra regions of a
+/// variant such as the diagonals of Sudoku-X.
+///
+/// # Panics
+///
+/// When `geometry` is not the one of a 9x9 grid.
+pub fn check_grid_at(g: Grid, geometry: &Geometry, index: usize) -> bool {
+    assert_eq!(geometry.size(), 9, "grids have 9 rows");
+    geometry
+        .houses_of(index)
+        .iter()
+        .all(|&house| check_no_redundant_value(g, &geometry.houses()[house]))
+}
+
+fn check_no_redundant_value(grid: Grid, val: &[usize]) -> bool {
+    let mut checked = Candidates::NONE;
+    for &v in val {
+        if let Some(cell_value) = grid.value(v) {
+            if checked.contains(cell_value) {
+                return false;
+            }
+            checked.insert(cell_value);
+        }
+    }
+    true
+}
+
+/// Two cells of a house holding the same value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub cells: [usize; 2],
+    pub value: usize,
+    pub house: House,
+    /// Number of rows and columns of the grid, 9 for a classic sudoku.
+    pub size: usize,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} and {} both hold {} in {}",
+            cell_name_in(self.cells[0], self.size),
+            cell_name_in(self.cells[1], self.size),
+            self.value + 1,
+            self.house
+        )
+    }
+}
+
+/// Lists every pair of cells holding the same value in a row, column or
+/// box. A grid without conflict can still have no solution.
+pub fn find_conflicts(g: &Grid) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    for (index, adjacent) in HOUSE_PEERS.iter().enumerate() {
+        let value = match g.value(index) {
+            Some(value) => value,
+            None => continue,
+        };
+        let (row, column) = (index / 9, index % 9);
+        let houses = [
+            House::Row(row),
+            House::Column(column),
+            House::Box(box_of(index)),
+        ];
+
+        for (adj_cells, &house) in adjacent.iter().zip(houses.iter()) {
+            for &other in adj_cells.iter().filter(|&&other| other > index) {
+                if g.value(other) == Some(value) {
+                    conflicts.push(Conflict {
+                        cells: [index, other],
+                        value,
+                        house,
+                        size: 9,
+                    });
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+/// Why a grid could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A character other than a digit, `.`, `_` or whitespace.
+    InvalidCharacter(char),
+    /// A multi-character symbol that is not the number of a value.
+    InvalidSymbol(String),
+    /// More cells than the grid has, with its number of cells.
+    TooManyCells(usize),
+    /// Fewer cells than the grid has, with the number found and expected.
+    TooFewCells(usize, usize),
+    /// A `(` of pencil marks without its `)`.
+    UnclosedParenthesis,
+    /// A jigsaw layout whose regions are not a valid grid.
+    InvalidRegions(RegionError),
+}
+
+/// Error returned by [`parse_grid`] and [`parse_pencil_marks`], located by
+/// line and column (both starting at 1) in the parsed text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            ParseErrorKind::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            ParseErrorKind::InvalidSymbol(symbol) => write!(f, "invalid symbol {:?}", symbol),
+            ParseErrorKind::TooManyCells(expected) => write!(f, "more than {} cells", expected),
+            ParseErrorKind::TooFewCells(found, expected) => {
+                write!(f, "only {} cells, expected {}", found, expected)
+            }
+            ParseErrorKind::UnclosedParenthesis => write!(f, "unclosed parenthesis"),
+            ParseErrorKind::InvalidRegions(err) => write!(f, "invalid regions: {}", err),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses a grid written with digits `1`-`9` and `0`, `_` or `.` for empty
+/// cells. Whitespace is ignored, so both one-line and multi-line layouts are
+/// read.
+pub fn parse_grid(grid_string: &str) -> Result<Grid, ParseError> {
+    let mut grid = Grid::empty();
+
+    let mut i = 0;
+    let (mut line, mut column) = (1, 0);
+    for c in grid_string.chars() {
+        column += 1;
+        if c == '\n' {
+            line += 1;
+            column = 0;
+            continue;
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let error = |kind| Err(ParseError { line, column, kind });
+        let value = match c {
+            '0' | '_' | '.' => None,
+            '1'..='9' => Some(c as usize - '1' as usize),
+            _ => return error(ParseErrorKind::InvalidCharacter(c)),
+        };
+        if i == 81 {
+            return error(ParseErrorKind::TooManyCells(81));
+        }
+        if let Some(value) = value {
+            grid.place(i, value);
+        }
+        i += 1;
+    }
+
+    if i < 81 {
+        return Err(ParseError {
+            line,
+            column: column + 1,
+            kind: ParseErrorKind::TooFewCells(i, 81),
+        });
+    }
+
+    Ok(grid)
+}
+
+/// Characters drawing the borders of pencil-mark grids, ignored between
+/// cells.
+const BORDER_CHARACTERS: [char; 7] = ['|', '-', '+', '.', ':', '\'', '='];
+
+/// Parses a grid of pencil marks, where each cell is a group of digits: its
+/// value when there is a single digit, else its candidates. Candidates can
+/// also be put between parentheses as [`format_grid`] prints them, `(7)`
+/// being an empty cell with 7 as its only candidate. Cells are separated by
+/// whitespace or the `|-+.:'=` characters of the borders.
+///
+/// Empty cells keep the candidates given, even the ones ruled out by the
+/// values of their peers.
+pub fn parse_pencil_marks(grid_string: &str) -> Result<Grid, ParseError> {
+    let mut cells = Vec::with_capacity(81);
+
+    let (mut line, mut column) = (1, 0);
+    // position of the `(` when reading candidates between parentheses
+    let mut open = None;
+    let mut candidates = Candidates::NONE;
+    let mut digits = 0;
+    for c in grid_string.chars() {
+        column += 1;
+        let error = |kind| Err(ParseError { line, column, kind });
+
+        let cell = match c {
+            '1'..='9' => {
+                candidates.insert(c as usize - '1' as usize);
+                digits += 1;
+                None
+            }
+            '(' if open.is_none() && digits == 0 => {
+                open = Some((line, column));
+                None
+            }
+            ')' if open.is_some() => {
+                open = None;
+                Some(CellValue::Possibilities(candidates))
+            }
+            c if open.is_some() && c.is_whitespace() => None,
+            c if open.is_none() && (c.is_whitespace() || BORDER_CHARACTERS.contains(&c)) => {
+                (digits > 0).then(|| pencil_mark_cell(candidates, digits))
+            }
+            _ => return error(ParseErrorKind::InvalidCharacter(c)),
+        };
+        if let Some(cell) = cell {
+            if cells.len() == 81 {
+                return error(ParseErrorKind::TooManyCells(81));
+            }
+            cells.push(cell);
+            candidates = Candidates::NONE;
+            digits = 0;
+        }
+
+        if c == '\n' {
+            line += 1;
+            column = 0;
+        }
+    }
+
+    if let Some((line, column)) = open {
+        return Err(ParseError {
+            line,
+            column,
+            kind: ParseErrorKind::UnclosedParenthesis,
+        });
+    }
+    if digits > 0 {
+        if cells.len() == 81 {
+            return Err(ParseError {
+                line,
+                column,
+                kind: ParseErrorKind::TooManyCells(81),
+            });
+        }
+        cells.push(pencil_mark_cell(candidates, digits));
+    }
+    if cells.len() < 81 {
+        return Err(ParseError {
+            line,
+            column: column + 1,
+            kind: ParseErrorKind::TooFewCells(cells.len(), 81),
+        });
+    }
+
+    let mut grid = Grid::empty();
+    for (index, &cell) in cells.iter().enumerate() {
+        if let CellValue::Value(value) = cell {
+            grid.place(index, value);
+        }
+    }
+    for (index, &cell) in cells.iter().enumerate() {
+        if let CellValue::Possibilities(candidates) = cell {
+            grid.set_candidates(index, candidates);
+        }
+    }
+    Ok(grid)
+}
+
+/// The cell written with `digits` digits, those of `candidates`.
+fn pencil_mark_cell(candidates: Candidates, digits: usize) -> CellValue {
+    match candidates.single() {
+        Some(value) if digits == 1 => CellValue::Value(value),
+        _ => CellValue::Possibilities(candidates),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: &str =
+        "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......";
+
+    fn error(line: usize, column: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError { line, column, kind }
+    }
+
+    #[test]
+    fn parsed_grids() {
+        let grid = parse_grid(GRID).unwrap();
+        assert_eq!(grid.value(0), Some(3));
+        assert_eq!(grid.value(1), None);
+        assert_eq!(grid.value_count(), 17);
+        // empty cells and layouts
+        let multi_line = GRID
+            .replace('.', "0")
+            .as_bytes()
+            .chunks(9)
+            .map(|row| String::from_utf8_lossy(row).into_owned())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(parse_grid(&multi_line).unwrap(), grid);
+        assert_eq!(parse_grid(&GRID.replace('.', "_")).unwrap(), grid);
+    }
+
+    #[test]
+    fn grid_errors() {
+        let invalid = format!("{}x{}", &GRID[..20], &GRID[21..]);
+        assert_eq!(
+            parse_grid(&invalid).unwrap_err(),
+            error(1, 21, ParseErrorKind::InvalidCharacter('x'))
+        );
+        // the lines and columns of multi-line grids, from 1
+        let multi_line = "4.....8.5\n .3......x\n";
+        assert_eq!(
+            parse_grid(multi_line).unwrap_err(),
+            error(2, 10, ParseErrorKind::InvalidCharacter('x'))
+        );
+        assert_eq!(
+            parse_grid(&GRID[..80]).unwrap_err(),
+            error(1, 81, ParseErrorKind::TooFewCells(80, 81))
+        );
+        assert_eq!(
+            parse_grid("123\n45\n").unwrap_err(),
+            error(3, 1, ParseErrorKind::TooFewCells(5, 81))
+        );
+        assert_eq!(
+            parse_grid(&format!("{} 1", GRID)).unwrap_err(),
+            error(1, 83, ParseErrorKind::TooManyCells(81))
+        );
+        assert_eq!(
+            parse_grid("123\n4x").unwrap_err().to_string(),
+            "line 2, column 2: invalid character 'x'"
+        );
+    }
+
+    #[test]
+    fn pencil_mark_errors() {
+        let grid = parse_grid(GRID).unwrap();
+        let marks = format_grid(&grid, true);
+        assert_eq!(parse_pencil_marks(&marks).unwrap(), grid);
+
+        assert_eq!(
+            parse_pencil_marks("4 (12 3").unwrap_err(),
+            error(1, 3, ParseErrorKind::UnclosedParenthesis)
+        );
+        assert_eq!(
+            parse_pencil_marks("4 12x").unwrap_err(),
+            error(1, 5, ParseErrorKind::InvalidCharacter('x'))
+        );
+    }
+}
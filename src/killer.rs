@@ -0,0 +1,622 @@
+//! Killer sudokus: cages of cells whose values add up to a sum, usually
+//! without repeating a value. Cages are read from lines such as
+//! `15 r1c1 r1c2 r2c1`, or from JSON, and are added to a [`Geometry`] with
+//! [`Geometry::with_cages`](crate::Geometry::with_cages).
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+
+use crate::board::{Board, BoxShape, Symbols, ValueSet};
+use crate::generator::generate_full_grid;
+use crate::geometry::Geometry;
+use crate::grid::cell_name_in;
+use crate::solver::{SolutionCount, Solver, SolverConfig};
+
+/// Cells whose values add up to `sum`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cage {
+    pub cells: Vec<usize>,
+    pub sum: usize,
+    /// The cells hold different values, as in most killer sudokus.
+    pub no_repeat: bool,
+}
+
+/// Sets of `count` different values of a board of `size` values, the
+/// values being numbered from 0 as in [`ValueSet`], adding up to `sum` when
+/// counted from 1.
+pub fn combinations(sum: usize, count: usize, size: usize) -> Vec<ValueSet> {
+    let mut found = vec![];
+    add_combinations(sum, count, 0, size, ValueSet::NONE, &mut found);
+    found
+}
+
+/// Adds to `found` the combinations made of `chosen` and of `count` values
+/// of `first` to `size` - 1 adding up to `sum`.
+fn add_combinations(
+    sum: usize,
+    count: usize,
+    first: usize,
+    size: usize,
+    chosen: ValueSet,
+    found: &mut Vec<ValueSet>,
+) {
+    if count == 0 {
+        if sum == 0 {
+            found.push(chosen);
+        }
+        return;
+    }
+    for value in first..size {
+        // the smallest values left, counted from 1, already exceed the sum
+        let smallest = count * (value + 1) + count * (count - 1) / 2;
+        if smallest > sum {
+            break;
+        }
+        let mut with_value = chosen;
+        with_value.insert(value);
+        add_combinations(
+            sum - value - 1,
+            count - 1,
+            value + 1,
+            size,
+            with_value,
+            found,
+        );
+    }
+}
+
+/// Checks that `cages` cover every cell of a board of `size` values once,
+/// and that each one can reach its sum. The sums must then add up to the sum
+/// of the whole grid.
+pub fn check_cages(cages: &[Cage], size: usize) -> Result<(), CageError> {
+    let mut cage_of = vec![None; size * size];
+    for (number, cage) in cages.iter().enumerate() {
+        if cage.cells.is_empty() {
+            return Err(CageError::EmptyCage(number + 1));
+        }
+        for &cell in &cage.cells {
+            if cell >= cage_of.len() {
+                return Err(CageError::InvalidCell(number + 1, cell.to_string()));
+            }
+            if cage_of[cell].replace(number).is_some() {
+                return Err(CageError::Overlap(cell_name_in(cell, size)));
+            }
+        }
+        let count = cage.cells.len();
+        let feasible = if cage.no_repeat {
+            !combinations(cage.sum, count, size).is_empty()
+        } else {
+            (count..=count * size).contains(&cage.sum)
+        };
+        if !feasible {
+            return Err(CageError::Infeasible(number + 1, cage.sum, count));
+        }
+    }
+    if let Some(cell) = cage_of.iter().position(Option::is_none) {
+        return Err(CageError::Uncovered(cell_name_in(cell, size)));
+    }
+
+    let total = cages.iter().map(|cage| cage.sum).sum();
+    let expected = size * size * (size + 1) / 2;
+    if total != expected {
+        return Err(CageError::Total(total, expected));
+    }
+    Ok(())
+}
+
+/// Parses the cages of a board of `size` values. Text gives one cage per
+/// line, its sum then its cells such as `r1c2`, ending with `repeats` when
+/// its values may repeat; blank lines and lines starting with `#` are
+/// ignored. JSON gives an object such as
+/// `{"cages": [{"sum": 15, "cells": ["r1c1", "r1c2"]}]}`, a cage having
+/// `"no_repeat": false` when its values may repeat.
+pub fn parse_cages(text: &str, size: usize) -> Result<Vec<Cage>, CageError> {
+    if text.trim_start().starts_with('{') {
+        return parse_json_cages(text, size);
+    }
+
+    let mut cages = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = cages.len() + 1;
+        let mut words = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty());
+        let sum = words.next().unwrap_or_default();
+        let sum = sum
+            .parse()
+            .map_err(|_| CageError::InvalidSum(number, sum.to_string()))?;
+        let mut cage = Cage {
+            cells: vec![],
+            sum,
+            no_repeat: true,
+        };
+        for word in words {
+            if word.eq_ignore_ascii_case("repeats") {
+                cage.no_repeat = false;
+            } else {
+                cage.cells.push(parse_cell(word, size, number)?);
+            }
+        }
+        cages.push(cage);
+    }
+    Ok(cages)
+}
+
+#[derive(Deserialize)]
+struct JsonCages {
+    cages: Vec<JsonCage>,
+}
+
+#[derive(Deserialize)]
+struct JsonCage {
+    sum: usize,
+    cells: Vec<String>,
+    #[serde(default = "JsonCage::no_repeat_default")]
+    no_repeat: bool,
+}
+
+impl JsonCage {
+    fn no_repeat_default() -> bool {
+        true
+    }
+}
+
+fn parse_json_cages(text: &str, size: usize) -> Result<Vec<Cage>, CageError> {
+    let json: JsonCages =
+        serde_json::from_str(text).map_err(|err| CageError::Json(err.to_string()))?;
+    json.cages
+        .into_iter()
+        .enumerate()
+        .map(|(i, cage)| {
+            Ok(Cage {
+                cells: cage
+                    .cells
+                    .iter()
+                    .map(|name| parse_cell(name, size, i + 1))
+                    .collect::<Result<_, _>>()?,
+                sum: cage.sum,
+                no_repeat: cage.no_repeat,
+            })
+        })
+        .collect()
+}
+
+/// Index of the cell named `r<row>c<column>` in cage `number`, rows and
+/// columns counting from 1.
+fn parse_cell(name: &str, size: usize, number: usize) -> Result<usize, CageError> {
+    let invalid = || CageError::InvalidCell(number, name.to_string());
+    let lower = name.to_ascii_lowercase();
+    let (row, column) = lower
+        .strip_prefix('r')
+        .and_then(|rest| rest.split_once('c'))
+        .ok_or_else(invalid)?;
+    let (row, column) = match (row.parse::<usize>(), column.parse::<usize>()) {
+        (Ok(row), Ok(column)) => (row, column),
+        _ => return Err(invalid()),
+    };
+    if !(1..=size).contains(&row) || !(1..=size).contains(&column) {
+        return Err(invalid());
+    }
+    Ok((row - 1) * size + column - 1)
+}
+
+/// The cages in the text format of [`parse_cages`], one per line.
+pub fn cages_to_text(cages: &[Cage], size: usize) -> String {
+    let mut text = String::new();
+    for cage in cages {
+        text.push_str(&cage.sum.to_string());
+        for &cell in &cage.cells {
+            text.push(' ');
+            text.push_str(&cell_name_in(cell, size));
+        }
+        if !cage.no_repeat {
+            text.push_str(" repeats");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Cuttings of the full grid tried by [`generate_killer`] before it splits
+/// the last one until it is unique.
+const KILLER_ATTEMPTS: usize = 20;
+
+/// Generates the cages of a classic killer sudoku without givens from
+/// `seed`. Cages of 2 to `max_cells` cells, as cut by [`generate_cages`],
+/// are cut from a random full grid until they leave it as the only solution.
+/// After [`KILLER_ATTEMPTS`] cuttings, the last one is made unique with
+/// [`split_until_unique`] instead, so that generation always ends.
+///
+/// # Panics
+///
+/// When `max_cells` is 0.
+pub fn generate_killer(seed: u64, max_cells: usize) -> Vec<Cage> {
+    generate_killer_in(seed, max_cells, KILLER_ATTEMPTS)
+}
+
+/// [`generate_killer`] splitting the cuttings after `attempts` of them.
+fn generate_killer_in(seed: u64, max_cells: usize, attempts: usize) -> Vec<Cage> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let full = generate_full_grid(seed);
+    let solution = Board::parse(&full.to_line(), BoxShape::CLASSIC, Symbols::Digits)
+        .expect("a full grid is a valid board");
+    let solver = Solver::with_config(SolverConfig {
+        parallel_branches: false,
+        ..SolverConfig::default()
+    });
+    let mut cages = vec![];
+    for _ in 0..attempts.max(1) {
+        cages = generate_cages(&solution, rng.gen(), max_cells);
+        if killer_solutions(&solver, &cages).count == 1 {
+            return cages;
+        }
+    }
+    split_until_unique(&solver, &solution, cages)
+}
+
+/// Splits `cages`, cut from the complete `solution`, until it is their only
+/// solution: a cell where two of their solutions differ becomes a cage of
+/// its own, and the rest of its cage a cage for each of its pieces. Each
+/// split adds a cage, so that at worst every cell ends up alone.
+pub fn split_until_unique(solver: &Solver, solution: &Board, mut cages: Vec<Cage>) -> Vec<Cage> {
+    let size = solution.size();
+    let value = |cell| solution.value(cell).expect("the solution is complete");
+    loop {
+        let found = killer_solutions(solver, &cages);
+        let [first, second] = match &found.solutions[..] {
+            [first, second] => [first, second],
+            _ => return cages,
+        };
+        let cell = (0..solution.cell_count())
+            .find(|&cell| first.value(cell) != second.value(cell))
+            .expect("two solutions differ");
+        let caged = cages
+            .iter()
+            .position(|cage| cage.cells.contains(&cell))
+            .expect("the cages cover the grid");
+        let mut rest = cages.swap_remove(caged).cells;
+        rest.retain(|&other| other != cell);
+        cages.push(Cage {
+            cells: vec![cell],
+            sum: value(cell) + 1,
+            no_repeat: true,
+        });
+
+        // the rest of the cage may be in several pieces
+        while let Some(start) = rest.pop() {
+            let mut cells = vec![start];
+            let mut next = 0;
+            while next < cells.len() {
+                for neighbour in side_neighbours(cells[next], size) {
+                    if let Some(index) = rest.iter().position(|&other| other == neighbour) {
+                        cells.push(rest.swap_remove(index));
+                    }
+                }
+                next += 1;
+            }
+            cells.sort_unstable();
+            cages.push(Cage {
+                sum: cells.iter().map(|&cell| value(cell) + 1).sum(),
+                cells,
+                no_repeat: true,
+            });
+        }
+        cages.sort_by_key(|cage| cage.cells[0]);
+    }
+}
+
+/// Up to two solutions of the classic killer sudoku of `cages`.
+fn killer_solutions(solver: &Solver, cages: &[Cage]) -> SolutionCount<Board> {
+    let geometry = Geometry::new(BoxShape::CLASSIC)
+        .with_cages(cages.to_vec())
+        .expect("cages cut from a full grid are valid");
+    let puzzle = Board::new(Arc::new(geometry), Symbols::Digits);
+    solver.count_board_solutions(&puzzle, 2)
+}
+
+/// Cuts the complete `solution` into cages of 2 to `max_cells` cells from
+/// `seed`. Each cage is in one piece and holds different values, its sum
+/// being the one of its values in `solution`. A cell left alone joins a
+/// neighbouring cage when one has room and lacks its value, and stays a cage
+/// of its own otherwise.
+///
+/// # Panics
+///
+/// When `max_cells` is 0.
+pub fn generate_cages(solution: &Board, seed: u64, max_cells: usize) -> Vec<Cage> {
+    assert!(max_cells > 0, "cages have at least one cell");
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let size = solution.size();
+    let value = |cell| solution.value(cell).expect("the solution is complete");
+
+    // the cells of each cage and the values they hold, and the cage of each
+    // cell once caged
+    let mut cages: Vec<(Vec<usize>, ValueSet)> = vec![];
+    let mut cage_of = vec![None; solution.cell_count()];
+    let mut starts = (0..solution.cell_count()).collect::<Vec<_>>();
+    starts.shuffle(&mut rng);
+
+    for start in starts {
+        if cage_of[start].is_some() {
+            continue;
+        }
+        let target = rng.gen_range(max_cells.min(2)..=max_cells);
+        let mut cells = vec![start];
+        let mut values = ValueSet::only(value(start));
+        cage_of[start] = Some(cages.len());
+        while cells.len() < target {
+            let neighbours = cells
+                .iter()
+                .flat_map(|&cell| side_neighbours(cell, size))
+                .filter(|&cell| cage_of[cell].is_none() && !values.contains(value(cell)))
+                .collect::<Vec<_>>();
+            let Some(&next) = neighbours.choose(&mut rng) else {
+                break;
+            };
+            cells.push(next);
+            values.insert(value(next));
+            cage_of[next] = Some(cages.len());
+        }
+        cages.push((cells, values));
+    }
+
+    // cells left alone join the smallest neighbouring cage that can take
+    // them, which may be another lone cell
+    for alone in 0..cages.len() {
+        if cages[alone].0.len() != 1 {
+            continue;
+        }
+        let cell = cages[alone].0[0];
+        let joined = side_neighbours(cell, size)
+            .filter_map(|neighbour| cage_of[neighbour])
+            .filter(|&cage| {
+                let (ref cells, values) = cages[cage];
+                cage != alone && cells.len() < max_cells && !values.contains(value(cell))
+            })
+            .min_by_key(|&cage| cages[cage].0.len());
+        if let Some(joined) = joined {
+            cages[alone].0.clear();
+            cages[joined].0.push(cell);
+            cages[joined].1.insert(value(cell));
+            cage_of[cell] = Some(joined);
+        }
+    }
+
+    let mut cages = cages
+        .into_iter()
+        .filter(|(cells, _)| !cells.is_empty())
+        .map(|(mut cells, _)| {
+            cells.sort_unstable();
+            Cage {
+                sum: cells.iter().map(|&cell| value(cell) + 1).sum(),
+                cells,
+                no_repeat: true,
+            }
+        })
+        .collect::<Vec<_>>();
+    cages.sort_by_key(|cage| cage.cells[0]);
+    cages
+}
+
+/// Cells sharing a side with the cell at `index` of a grid of `size`
+/// columns.
+fn side_neighbours(index: usize, size: usize) -> impl Iterator<Item = usize> {
+    let (row, column) = (index / size, index % size);
+    [
+        (row > 0).then(|| index - size),
+        (row + 1 < size).then(|| index + size),
+        (column > 0).then(|| index - 1),
+        (column + 1 < size).then(|| index + 1),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// Why cages were rejected. Cages are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CageError {
+    /// A cage, with its number, whose sum is not a number.
+    InvalidSum(usize, String),
+    /// A cage, with its number, naming a cell outside of the grid.
+    InvalidCell(usize, String),
+    /// Malformed JSON, with the message of the parser.
+    Json(String),
+    /// A cage, with its number, without any cell.
+    EmptyCage(usize),
+    /// A cell, with its name, in two cages.
+    Overlap(String),
+    /// A cell, with its name, in no cage.
+    Uncovered(String),
+    /// A cage, with its number, whose sum, given next, cannot be reached by
+    /// its number of cells.
+    Infeasible(usize, usize, usize),
+    /// Sums of the cages not adding up to the sum of the grid, with the
+    /// sums found and expected.
+    Total(usize, usize),
+}
+
+impl fmt::Display for CageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CageError::InvalidSum(number, ref sum) => {
+                write!(f, "cage {}: invalid sum {:?}", number, sum)
+            }
+            CageError::InvalidCell(number, ref cell) => {
+                write!(f, "cage {}: invalid cell {:?}", number, cell)
+            }
+            CageError::Json(ref message) => write!(f, "invalid JSON: {}", message),
+            CageError::EmptyCage(number) => write!(f, "cage {} has no cell", number),
+            CageError::Overlap(ref cell) => write!(f, "{} is in two cages", cell),
+            CageError::Uncovered(ref cell) => write!(f, "{} is in no cage", cell),
+            CageError::Infeasible(number, sum, cells) => write!(
+                f,
+                "cage {} cannot add up to {} with {} cells",
+                number, sum, cells
+            ),
+            CageError::Total(found, expected) => {
+                write!(f, "the cages add up to {}, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Error for CageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[usize]) -> ValueSet {
+        let mut set = ValueSet::NONE;
+        for &value in values {
+            // values are numbered from 0
+            set.insert(value - 1);
+        }
+        set
+    }
+
+    fn cage(cells: &[usize], sum: usize) -> Cage {
+        Cage {
+            cells: cells.to_vec(),
+            sum,
+            no_repeat: true,
+        }
+    }
+
+    /// The 4x4 grid cut into its rows, adding up to 10 each.
+    fn rows() -> Vec<Cage> {
+        (0..4)
+            .map(|row| cage(&[4 * row, 4 * row + 1, 4 * row + 2, 4 * row + 3], 10))
+            .collect()
+    }
+
+    #[test]
+    fn known_combinations() {
+        assert_eq!(combinations(3, 2, 9), [values(&[1, 2])]);
+        assert_eq!(combinations(17, 2, 9), [values(&[8, 9])]);
+        assert_eq!(
+            combinations(10, 3, 9),
+            [
+                values(&[1, 2, 7]),
+                values(&[1, 3, 6]),
+                values(&[1, 4, 5]),
+                values(&[2, 3, 5])
+            ]
+        );
+        assert_eq!(combinations(45, 9, 9), [ValueSet::all(9)]);
+        assert!(combinations(2, 2, 9).is_empty());
+        assert!(combinations(18, 2, 9).is_empty());
+    }
+
+    #[test]
+    fn checked_cages() {
+        assert_eq!(check_cages(&rows(), 4), Ok(()));
+
+        let mut overlap = rows();
+        overlap[0].cells.push(4);
+        overlap[0].no_repeat = false;
+        assert_eq!(
+            check_cages(&overlap, 4),
+            Err(CageError::Overlap("r2c1".to_string()))
+        );
+
+        let mut uncovered = rows();
+        uncovered[3].cells.pop();
+        uncovered[3].sum = 6;
+        assert_eq!(
+            check_cages(&uncovered, 4),
+            Err(CageError::Uncovered("r4c4".to_string()))
+        );
+
+        let mut infeasible = rows();
+        infeasible[1].sum = 17;
+        assert_eq!(
+            check_cages(&infeasible, 4),
+            Err(CageError::Infeasible(2, 17, 4))
+        );
+
+        let mut total = rows();
+        total[0].sum = 9;
+        total[0].no_repeat = false;
+        assert_eq!(check_cages(&total, 4), Err(CageError::Total(39, 40)));
+    }
+
+    #[test]
+    fn parsed_cages() {
+        let text = "# two cages\n3 r1c1 r1c2\n\n4 R2C1, r2c2 repeats\n";
+        assert_eq!(
+            parse_cages(text, 4),
+            Ok(vec![
+                cage(&[0, 1], 3),
+                Cage {
+                    no_repeat: false,
+                    ..cage(&[4, 5], 4)
+                }
+            ])
+        );
+        assert_eq!(parse_cages(&cages_to_text(&rows(), 4), 4), Ok(rows()));
+
+        let json = r#"{"cages": [
+            {"sum": 3, "cells": ["r1c1", "r1c2"]},
+            {"sum": 4, "cells": ["r2c1", "r2c2"], "no_repeat": false}
+        ]}"#;
+        assert_eq!(parse_cages(json, 4), parse_cages(text, 4));
+
+        assert_eq!(
+            parse_cages("3 r1c1\nx r1c2", 4),
+            Err(CageError::InvalidSum(2, "x".to_string()))
+        );
+        assert_eq!(
+            parse_cages("3 r1c5", 4),
+            Err(CageError::InvalidCell(1, "r1c5".to_string()))
+        );
+        assert!(matches!(parse_cages("{", 4), Err(CageError::Json(_))));
+    }
+
+    #[test]
+    fn generated_killers_are_proper() {
+        for seed in 0..3 {
+            let cages = generate_killer(seed, 4);
+            assert_eq!(check_cages(&cages, 9), Ok(()));
+            let alone = cages.iter().filter(|cage| cage.cells.len() == 1).count();
+            assert!(alone * 10 <= cages.len(), "{} lone cells", alone);
+
+            let geometry = Geometry::new(BoxShape::CLASSIC).with_cages(cages).unwrap();
+            let puzzle = Board::new(Arc::new(geometry), Symbols::Digits);
+            assert_eq!(Solver::new().count_board_solutions(&puzzle, 2).count, 1);
+        }
+    }
+
+    #[test]
+    fn generation_ends_with_a_unique_killer() {
+        // the first cutting of seed 2 has several solutions, and only one
+        // attempt is allowed before splitting it
+        let seed = 2;
+        let solver = Solver::new();
+        let solution = full_board(seed);
+        let first = generate_cages(&solution, ChaCha8Rng::seed_from_u64(seed).gen(), 4);
+        assert_eq!(killer_solutions(&solver, &first).count, 2);
+
+        let cages = generate_killer_in(seed, 4, 1);
+        assert_eq!(check_cages(&cages, 9), Ok(()));
+        assert!(cages.len() > first.len());
+        let found = killer_solutions(&solver, &cages);
+        assert_eq!(found.count, 1);
+        assert_eq!(found.solutions[0].to_string(), solution.to_string());
+    }
+
+    /// The full grid killers of `seed` are cut from.
+    fn full_board(seed: u64) -> Board {
+        let full = generate_full_grid(seed);
+        Board::parse(&full.to_line(), BoxShape::CLASSIC, Symbols::Digits).unwrap()
+    }
+}
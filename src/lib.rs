@@ -0,0 +1,54 @@
+//! Sudoku solving library.
+//!
+//! It is inspired by Peter Norvig research at http://norvig.com/sudoku.html
+//! and uses rayon to explore the search tree in parallel. Besides the
+//! classic 9x9 [`Sudoku`], [`Board`]s of other sizes are solved too.
+//!
+//! ```
+//! use sudoku_solver::{Solver, Sudoku};
+//!
+//! let sudoku = Sudoku::parse(
+//!     "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......",
+//! )
+//! .unwrap();
+//! let result = Solver::new().solve(&sudoku);
+//! assert!(result.solution().unwrap().is_complete());
+//! ```
+
+extern crate rand;
+extern crate rand_chacha;
+extern crate rayon;
+extern crate serde;
+extern crate serde_json;
+
+pub mod bench;
+pub mod board;
+#[cfg(test)]
+mod constants;
+pub mod dlx;
+pub mod formats;
+pub mod generator;
+pub mod geometry;
+pub mod grid;
+pub mod killer;
+pub mod logic;
+pub mod rating;
+pub mod solver;
+pub mod sudoku;
+
+pub use crate::board::{Board, BoxShape, Symbols, ValueSet};
+pub use crate::dlx::{count_solutions_dlx, solve_grid_dlx};
+pub use crate::formats::FileFormat;
+pub use crate::generator::{generate, GeneratorOptions, Symmetry};
+pub use crate::geometry::{Geometry, RegionError};
+pub use crate::grid::{
+    find_conflicts, Candidates, CellValue, Conflict, Grid, House, ParseError, ParseErrorKind,
+};
+pub use crate::killer::{Cage, CageError};
+pub use crate::logic::{solve_logically, LogicalSolution, Step, Technique};
+pub use crate::rating::{rate, Difficulty, Rating};
+pub use crate::solver::{
+    count_solutions, solve_grid, CancellationToken, Engine, Interruption, Outcome, SolutionCount,
+    SolveResult, SolveStats, Solver, SolverConfig,
+};
+pub use crate::sudoku::Sudoku;
@@ -0,0 +1,917 @@
+//! Step by step solver using the techniques a human would apply, so that
+//! each placed value comes with an explanation.
+
+use std::fmt;
+
+use crate::geometry::box_of;
+use crate::grid::{cell_name, Candidates, Grid, House};
+use crate::solver::get_adjacent_cells;
+use crate::sudoku::Sudoku;
+
+/// Logical techniques, from the easiest to the hardest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Technique {
+    HiddenSingle,
+    NakedSingle,
+    PointingPair,
+    BoxLineReduction,
+    NakedPair,
+    XWing,
+    HiddenPair,
+    NakedTriple,
+    Swordfish,
+    HiddenTriple,
+    XYWing,
+    SimpleColoring,
+    NakedQuad,
+    HiddenQuad,
+}
+
+impl Technique {
+    /// Every technique, in the order the solver tries them.
+    pub const ALL: [Technique; 14] = [
+        Technique::HiddenSingle,
+        Technique::NakedSingle,
+        Technique::PointingPair,
+        Technique::BoxLineReduction,
+        Technique::NakedPair,
+        Technique::XWing,
+        Technique::HiddenPair,
+        Technique::NakedTriple,
+        Technique::Swordfish,
+        Technique::HiddenTriple,
+        Technique::XYWing,
+        Technique::SimpleColoring,
+        Technique::NakedQuad,
+        Technique::HiddenQuad,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Technique::HiddenSingle => "hidden single",
+            Technique::NakedSingle => "naked single",
+            Technique::PointingPair => "pointing pair",
+            Technique::BoxLineReduction => "box/line reduction",
+            Technique::NakedPair => "naked pair",
+            Technique::XWing => "X-Wing",
+            Technique::HiddenPair => "hidden pair",
+            Technique::NakedTriple => "naked triple",
+            Technique::Swordfish => "Swordfish",
+            Technique::HiddenTriple => "hidden triple",
+            Technique::XYWing => "XY-Wing",
+            Technique::SimpleColoring => "simple coloring",
+            Technique::NakedQuad => "naked quad",
+            Technique::HiddenQuad => "hidden quad",
+        }
+    }
+
+    fn apply(self, grid: &Grid) -> Option<Step> {
+        match self {
+            Technique::HiddenSingle => hidden_single(grid),
+            Technique::NakedSingle => naked_single(grid),
+            Technique::PointingPair => pointing(grid),
+            Technique::BoxLineReduction => box_line_reduction(grid),
+            Technique::NakedPair => naked_subset(grid, 2, self),
+            Technique::NakedTriple => naked_subset(grid, 3, self),
+            Technique::NakedQuad => naked_subset(grid, 4, self),
+            Technique::HiddenPair => hidden_subset(grid, 2, self),
+            Technique::HiddenTriple => hidden_subset(grid, 3, self),
+            Technique::HiddenQuad => hidden_subset(grid, 4, self),
+            Technique::XWing => fish(grid, 2, self),
+            Technique::Swordfish => fish(grid, 3, self),
+            Technique::XYWing => xy_wing(grid),
+            Technique::SimpleColoring => simple_coloring(grid),
+        }
+    }
+}
+
+impl fmt::Display for Technique {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a step changes in the grid. Values are 0-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Deduction {
+    /// Places a value in a cell.
+    Place { cell: usize, value: usize },
+    /// Removes possibilities, as `(cell, value)` pairs.
+    Eliminate(Vec<(usize, usize)>),
+}
+
+/// One deduction, with the technique that justifies it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub technique: Technique,
+    pub deduction: Deduction,
+    /// Human readable explanation, such as `r3c5 = 7 by hidden single in box 2`.
+    pub explanation: String,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.explanation)
+    }
+}
+
+/// Result of [`solve_logically`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogicalSolution {
+    pub steps: Vec<Step>,
+    /// The grid once no technique applies anymore, with the possibilities
+    /// left in the empty cells.
+    pub sudoku: Sudoku,
+}
+
+impl LogicalSolution {
+    /// Returns true when the techniques were enough to fill the grid.
+    pub fn is_solved(&self) -> bool {
+        (0..81).all(|index| self.sudoku.grid().is_value(index))
+    }
+
+    /// The hardest technique the solution needed.
+    pub fn hardest_technique(&self) -> Option<Technique> {
+        self.steps.iter().map(|step| step.technique).max()
+    }
+}
+
+/// Solves `sudoku` using logical techniques only, recording every step.
+///
+/// At each step the easiest applicable technique is used. The search stops
+/// when the grid is full, or when no technique applies anymore.
+pub fn solve_logically(sudoku: &Sudoku) -> LogicalSolution {
+    let mut grid = *sudoku.grid();
+    for index in 0..81 {
+        let poss = grid.candidates(index) & grid.allowed(index);
+        grid.set_candidates(index, poss);
+    }
+
+    let mut steps = vec![];
+    while !is_stuck(&grid) {
+        let step = Technique::ALL
+            .iter()
+            .find_map(|technique| technique.apply(&grid));
+
+        match step {
+            Some(step) => {
+                apply_deduction(&mut grid, &step.deduction);
+                steps.push(step);
+            }
+            None => break,
+        }
+    }
+
+    LogicalSolution {
+        steps,
+        sudoku: Sudoku::from_grid(grid),
+    }
+}
+
+/// A grid is stuck when it is full, or when a cell has no possibility left.
+fn is_stuck(grid: &Grid) -> bool {
+    let mut empty_cells = (0..81).filter(|&index| !grid.is_value(index)).peekable();
+    empty_cells.peek().is_none() || empty_cells.any(|index| grid.candidates(index).is_empty())
+}
+
+fn apply_deduction(grid: &mut Grid, deduction: &Deduction) {
+    match *deduction {
+        Deduction::Place { cell, value } => grid.place(cell, value),
+        Deduction::Eliminate(ref eliminations) => {
+            for &(cell, value) in eliminations {
+                grid.remove_candidate(cell, value);
+            }
+        }
+    }
+}
+
+fn cell_names(cells: &[usize]) -> String {
+    cells
+        .iter()
+        .map(|&c| cell_name(c))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn value_names(values: &[usize]) -> String {
+    values
+        .iter()
+        .map(|v| (v + 1).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn elimination_names(eliminations: &[(usize, usize)]) -> String {
+    eliminations
+        .iter()
+        .map(|&(cell, value)| format!("{}<>{}", cell_name(cell), value + 1))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn has_possibility(grid: &Grid, cell: usize, value: usize) -> bool {
+    grid.candidates(cell).contains(value)
+}
+
+fn possibilities(grid: &Grid, cell: usize) -> Vec<usize> {
+    grid.candidates(cell).iter().collect()
+}
+
+/// Cells of `house` where `value` is still possible.
+fn positions(grid: &Grid, house: House, value: usize) -> Vec<usize> {
+    house
+        .cells()
+        .iter()
+        .cloned()
+        .filter(|&cell| has_possibility(grid, cell, value))
+        .collect()
+}
+
+fn sees(a: usize, b: usize) -> bool {
+    a != b && get_adjacent_cells(a).contains(&b)
+}
+
+fn combinations(items: &[usize], k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    if items.len() < k {
+        return vec![];
+    }
+
+    let mut res = vec![];
+    for (i, &first) in items.iter().enumerate() {
+        for mut rest in combinations(&items[i + 1..], k - 1) {
+            rest.insert(0, first);
+            res.push(rest);
+        }
+    }
+    res
+}
+
+fn elimination_step(
+    technique: Technique,
+    eliminations: Vec<(usize, usize)>,
+    reason: String,
+) -> Option<Step> {
+    if eliminations.is_empty() {
+        return None;
+    }
+
+    let explanation = format!("{}: {}", reason, elimination_names(&eliminations));
+    Some(Step {
+        technique,
+        deduction: Deduction::Eliminate(eliminations),
+        explanation,
+    })
+}
+
+fn naked_single(grid: &Grid) -> Option<Step> {
+    (0..81).find_map(|cell| match possibilities(grid, cell)[..] {
+        [value] => Some(Step {
+            technique: Technique::NakedSingle,
+            deduction: Deduction::Place { cell, value },
+            explanation: format!("{} = {} by naked single", cell_name(cell), value + 1),
+        }),
+        _ => None,
+    })
+}
+
+fn hidden_single(grid: &Grid) -> Option<Step> {
+    for house in House::all() {
+        for value in 0..9 {
+            if let [cell] = positions(grid, house, value)[..] {
+                return Some(Step {
+                    technique: Technique::HiddenSingle,
+                    deduction: Deduction::Place { cell, value },
+                    explanation: format!(
+                        "{} = {} by hidden single in {}",
+                        cell_name(cell),
+                        value + 1,
+                        house
+                    ),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// A value confined to one row or column of a box can be removed from the
+/// rest of that line.
+fn pointing(grid: &Grid) -> Option<Step> {
+    for b in 0..9 {
+        for value in 0..9 {
+            let cells = positions(grid, House::Box(b), value);
+            if cells.len() < 2 {
+                continue;
+            }
+
+            let lines = [
+                (
+                    cells.iter().all(|&c| c / 9 == cells[0] / 9),
+                    House::Row(cells[0] / 9),
+                ),
+                (
+                    cells.iter().all(|&c| c % 9 == cells[0] % 9),
+                    House::Column(cells[0] % 9),
+                ),
+            ];
+            for &(aligned, line) in &lines {
+                if !aligned {
+                    continue;
+                }
+                let eliminations = line
+                    .cells()
+                    .iter()
+                    .filter(|&&c| box_of(c) != b && has_possibility(grid, c, value))
+                    .map(|&c| (c, value))
+                    .collect();
+                let step = elimination_step(
+                    Technique::PointingPair,
+                    eliminations,
+                    format!("pointing {} in box {} along {}", value + 1, b + 1, line),
+                );
+                if step.is_some() {
+                    return step;
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A value confined to one box within a row or column can be removed from
+/// the rest of that box.
+fn box_line_reduction(grid: &Grid) -> Option<Step> {
+    let lines = (0..9).map(House::Row).chain((0..9).map(House::Column));
+    for line in lines {
+        for value in 0..9 {
+            let cells = positions(grid, line, value);
+            if cells.len() < 2 || cells.iter().any(|&c| box_of(c) != box_of(cells[0])) {
+                continue;
+            }
+
+            let b = box_of(cells[0]);
+            let eliminations = House::Box(b)
+                .cells()
+                .iter()
+                .filter(|c| !cells.contains(c) && has_possibility(grid, **c, value))
+                .map(|&c| (c, value))
+                .collect();
+            let step = elimination_step(
+                Technique::BoxLineReduction,
+                eliminations,
+                format!(
+                    "box/line reduction {} in {} within box {}",
+                    value + 1,
+                    line,
+                    b + 1
+                ),
+            );
+            if step.is_some() {
+                return step;
+            }
+        }
+    }
+    None
+}
+
+/// `size` cells of a house sharing exactly `size` possibilities: those
+/// values can be removed from the other cells of the house.
+fn naked_subset(grid: &Grid, size: usize, technique: Technique) -> Option<Step> {
+    for house in House::all() {
+        let cells = house.cells();
+        let candidates = cells
+            .iter()
+            .cloned()
+            .filter(|&c| {
+                let n = grid.candidates(c).len();
+                n >= 2 && n <= size
+            })
+            .collect::<Vec<_>>();
+
+        for subset in combinations(&candidates, size) {
+            let union = subset
+                .iter()
+                .fold(Candidates::NONE, |acc, &cell| acc | grid.candidates(cell));
+            let values = union.iter().collect::<Vec<_>>();
+            if values.len() != size {
+                continue;
+            }
+
+            let mut eliminations = vec![];
+            for &cell in cells.iter().filter(|c| !subset.contains(c)) {
+                for &value in &values {
+                    if has_possibility(grid, cell, value) {
+                        eliminations.push((cell, value));
+                    }
+                }
+            }
+            let step = elimination_step(
+                technique,
+                eliminations,
+                format!(
+                    "{} {} in {} at {}",
+                    technique,
+                    value_names(&values),
+                    house,
+                    cell_names(&subset)
+                ),
+            );
+            if step.is_some() {
+                return step;
+            }
+        }
+    }
+    None
+}
+
+/// `size` values of a house confined to the same `size` cells: the other
+/// possibilities of those cells can be removed.
+fn hidden_subset(grid: &Grid, size: usize, technique: Technique) -> Option<Step> {
+    for house in House::all() {
+        let candidates = (0..9)
+            .filter(|&v| {
+                let n = positions(grid, house, v).len();
+                n >= 1 && n <= size
+            })
+            .collect::<Vec<_>>();
+
+        for values in combinations(&candidates, size) {
+            let mut cells = values
+                .iter()
+                .flat_map(|&v| positions(grid, house, v))
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            cells.dedup();
+            if cells.len() != size {
+                continue;
+            }
+
+            let mut eliminations = vec![];
+            for &cell in &cells {
+                for value in possibilities(grid, cell) {
+                    if !values.contains(&value) {
+                        eliminations.push((cell, value));
+                    }
+                }
+            }
+            let step = elimination_step(
+                technique,
+                eliminations,
+                format!(
+                    "{} {} in {} at {}",
+                    technique,
+                    value_names(&values),
+                    house,
+                    cell_names(&cells)
+                ),
+            );
+            if step.is_some() {
+                return step;
+            }
+        }
+    }
+    None
+}
+
+/// X-Wing (`size` 2) and Swordfish (`size` 3): a value confined to `size`
+/// columns within `size` rows can be removed from the rest of those
+/// columns, and the other way around.
+fn fish(grid: &Grid, size: usize, technique: Technique) -> Option<Step> {
+    for value in 0..9 {
+        for &by_rows in &[true, false] {
+            let base = |i: usize| {
+                if by_rows {
+                    House::Row(i)
+                } else {
+                    House::Column(i)
+                }
+            };
+            let cover = |i: usize| {
+                if by_rows {
+                    House::Column(i)
+                } else {
+                    House::Row(i)
+                }
+            };
+            let cross_index = |cell: usize| if by_rows { cell % 9 } else { cell / 9 };
+
+            let lines = (0..9)
+                .filter(|&i| {
+                    let n = positions(grid, base(i), value).len();
+                    n >= 2 && n <= size
+                })
+                .collect::<Vec<_>>();
+
+            for base_lines in combinations(&lines, size) {
+                let mut cover_lines = base_lines
+                    .iter()
+                    .flat_map(|&i| positions(grid, base(i), value))
+                    .map(cross_index)
+                    .collect::<Vec<_>>();
+                cover_lines.sort_unstable();
+                cover_lines.dedup();
+                if cover_lines.len() != size {
+                    continue;
+                }
+
+                let mut eliminations = vec![];
+                for &i in &cover_lines {
+                    for cell in positions(grid, cover(i), value) {
+                        let base_index = if by_rows { cell / 9 } else { cell % 9 };
+                        if !base_lines.contains(&base_index) {
+                            eliminations.push((cell, value));
+                        }
+                    }
+                }
+                let (base_name, cover_name) = if by_rows {
+                    ("rows", "columns")
+                } else {
+                    ("columns", "rows")
+                };
+                let step = elimination_step(
+                    technique,
+                    eliminations,
+                    format!(
+                        "{} {} in {} {} and {} {}",
+                        technique,
+                        value + 1,
+                        base_name,
+                        value_names(&base_lines),
+                        cover_name,
+                        value_names(&cover_lines)
+                    ),
+                );
+                if step.is_some() {
+                    return step;
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A pivot with possibilities `xy` seeing two pincers `xz` and `yz`: one of
+/// the pincers holds `z`, so `z` can be removed from the cells seeing both.
+fn xy_wing(grid: &Grid) -> Option<Step> {
+    let bivalues = (0..81)
+        .filter(|&c| grid.candidates(c).len() == 2)
+        .collect::<Vec<_>>();
+
+    for &pivot in &bivalues {
+        let pivot_values = possibilities(grid, pivot);
+        let (x, y) = (pivot_values[0], pivot_values[1]);
+
+        let pincers = |shared: usize, other: usize| {
+            bivalues
+                .iter()
+                .cloned()
+                .filter(|&c| sees(pivot, c))
+                .filter_map(|c| match possibilities(grid, c)[..] {
+                    [a, b] if a == shared && b != other => Some((c, b)),
+                    [a, b] if b == shared && a != other => Some((c, a)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (xz, z) in pincers(x, y) {
+            for (yz, _) in pincers(y, x).into_iter().filter(|&(_, z2)| z2 == z) {
+                let eliminations = (0..81)
+                    .filter(|&c| c != pivot && sees(c, xz) && sees(c, yz))
+                    .filter(|&c| has_possibility(grid, c, z))
+                    .map(|c| (c, z))
+                    .collect();
+                let step = elimination_step(
+                    Technique::XYWing,
+                    eliminations,
+                    format!(
+                        "XY-Wing pivot {} with pincers {},{} on {}",
+                        cell_name(pivot),
+                        cell_name(xz),
+                        cell_name(yz),
+                        z + 1
+                    ),
+                );
+                if step.is_some() {
+                    return step;
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Colors chains of conjugate pairs of a value with two alternating colors:
+/// one of the colors holds the value. A color appearing twice in a house is
+/// false everywhere, and a cell seeing both colors cannot hold the value.
+fn simple_coloring(grid: &Grid) -> Option<Step> {
+    for value in 0..9 {
+        // links[c] are the cells forming a conjugate pair with c
+        let mut links: Vec<Vec<usize>> = vec![vec![]; 81];
+        for house in House::all() {
+            if let [a, b] = positions(grid, house, value)[..] {
+                links[a].push(b);
+                links[b].push(a);
+            }
+        }
+
+        let mut color: [Option<bool>; 81] = [None; 81];
+        for start in 0..81 {
+            if links[start].is_empty() || color[start].is_some() {
+                continue;
+            }
+
+            // color the chain containing start
+            let mut chain = vec![start];
+            color[start] = Some(true);
+            let mut i = 0;
+            while i < chain.len() {
+                let cell = chain[i];
+                for &next in &links[cell] {
+                    if color[next].is_none() {
+                        color[next] = color[cell].map(|c| !c);
+                        chain.push(next);
+                    }
+                }
+                i += 1;
+            }
+            if chain.len() < 3 {
+                continue;
+            }
+
+            let colored = |wanted: bool| {
+                chain
+                    .iter()
+                    .cloned()
+                    .filter(|&c| color[c] == Some(wanted))
+                    .collect::<Vec<_>>()
+            };
+            let (on, off) = (colored(true), colored(false));
+
+            // color wrap
+            for group in &[&on, &off] {
+                let conflict = group.iter().any(|&a| group.iter().any(|&b| sees(a, b)));
+                if conflict {
+                    let eliminations = group.iter().map(|&c| (c, value)).collect();
+                    return elimination_step(
+                        Technique::SimpleColoring,
+                        eliminations,
+                        format!(
+                            "simple coloring {} on {}, color wrap",
+                            value + 1,
+                            cell_names(&chain)
+                        ),
+                    );
+                }
+            }
+
+            // color trap
+            let eliminations = (0..81)
+                .filter(|c| !chain.contains(c) && has_possibility(grid, *c, value))
+                .filter(|&c| on.iter().any(|&a| sees(a, c)) && off.iter().any(|&b| sees(b, c)))
+                .map(|c| (c, value))
+                .collect();
+            let step = elimination_step(
+                Technique::SimpleColoring,
+                eliminations,
+                format!(
+                    "simple coloring {} on {}, color trap",
+                    value + 1,
+                    cell_names(&chain)
+                ),
+            );
+            if step.is_some() {
+                return step;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Solver;
+
+    /// An empty grid where `value` is no longer possible in `cells`.
+    fn without(value: usize, cells: impl IntoIterator<Item = usize>) -> Grid {
+        let mut grid = Grid::empty();
+        remove(&mut grid, value, cells);
+        grid
+    }
+
+    fn remove(grid: &mut Grid, value: usize, cells: impl IntoIterator<Item = usize>) {
+        for cell in cells {
+            grid.remove_candidate(cell, value);
+        }
+    }
+
+    fn with_candidates(cells: &[(usize, &[usize])]) -> Grid {
+        let mut grid = Grid::empty();
+        for &(cell, values) in cells {
+            grid.set_candidates(cell, values.iter().copied().collect());
+        }
+        grid
+    }
+
+    /// The `(cell, value)` pairs of every value of `values` in every cell.
+    fn pairs(cells: &[usize], values: &[usize]) -> Vec<(usize, usize)> {
+        cells
+            .iter()
+            .flat_map(|&cell| values.iter().map(move |&value| (cell, value)))
+            .collect()
+    }
+
+    fn assert_step(grid: &Grid, technique: Technique, deduction: Deduction, explanation: &str) {
+        let step = technique.apply(grid).unwrap();
+        assert_eq!(step.technique, technique);
+        assert_eq!(step.deduction, deduction);
+        assert!(
+            step.explanation.starts_with(explanation),
+            "{}",
+            step.explanation
+        );
+    }
+
+    #[test]
+    fn singles() {
+        let grid = with_candidates(&[(40, &[4])]);
+        assert_step(
+            &grid,
+            Technique::NakedSingle,
+            Deduction::Place { cell: 40, value: 4 },
+            "r5c5 = 5 by naked single",
+        );
+        // 7 only fits r1c4 in row 1
+        let grid = without(6, [0, 1, 2, 4, 5, 6, 7, 8]);
+        assert_step(
+            &grid,
+            Technique::HiddenSingle,
+            Deduction::Place { cell: 3, value: 6 },
+            "r1c4 = 7 by hidden single in row 1",
+        );
+        assert_eq!(Technique::HiddenSingle.apply(&Grid::empty()), None);
+    }
+
+    #[test]
+    fn intersections() {
+        // 3 of box 1 is in row 1
+        let grid = without(2, [9, 10, 11, 18, 19, 20]);
+        assert_step(
+            &grid,
+            Technique::PointingPair,
+            Deduction::Eliminate(pairs(&[3, 4, 5, 6, 7, 8], &[2])),
+            "pointing 3 in box 1 along row 1: r1c4<>3",
+        );
+        // 3 of row 1 is in box 1
+        let grid = without(2, [3, 4, 5, 6, 7, 8]);
+        assert_step(
+            &grid,
+            Technique::BoxLineReduction,
+            Deduction::Eliminate(pairs(&[9, 10, 11, 18, 19, 20], &[2])),
+            "box/line reduction 3 in row 1 within box 1: r2c1<>3",
+        );
+    }
+
+    #[test]
+    fn naked_subsets() {
+        let grid = with_candidates(&[(0, &[0, 1]), (3, &[0, 1])]);
+        assert_step(
+            &grid,
+            Technique::NakedPair,
+            Deduction::Eliminate(pairs(&[1, 2, 4, 5, 6, 7, 8], &[0, 1])),
+            "naked pair 1,2 in row 1 at r1c1,r1c4: r1c2<>1",
+        );
+        let grid = with_candidates(&[(0, &[0, 1]), (3, &[1, 2]), (6, &[0, 2])]);
+        assert_step(
+            &grid,
+            Technique::NakedTriple,
+            Deduction::Eliminate(pairs(&[1, 2, 4, 5, 7, 8], &[0, 1, 2])),
+            "naked triple 1,2,3 in row 1 at r1c1,r1c4,r1c7: r1c2<>1",
+        );
+        let grid = with_candidates(&[(0, &[0, 1]), (1, &[1, 2]), (3, &[2, 3]), (6, &[0, 3])]);
+        assert_step(
+            &grid,
+            Technique::NakedQuad,
+            Deduction::Eliminate(pairs(&[2, 4, 5, 7, 8], &[0, 1, 2, 3])),
+            "naked quad 1,2,3,4 in row 1 at r1c1,r1c2,r1c4,r1c7: r1c3<>1",
+        );
+    }
+
+    #[test]
+    fn hidden_subsets() {
+        let mut grid = Grid::empty();
+        for value in 0..2 {
+            remove(&mut grid, value, [1, 2, 4, 5, 6, 7, 8]);
+        }
+        assert_step(
+            &grid,
+            Technique::HiddenPair,
+            Deduction::Eliminate(pairs(&[0, 3], &[2, 3, 4, 5, 6, 7, 8])),
+            "hidden pair 1,2 in row 1 at r1c1,r1c4: r1c1<>3",
+        );
+        let mut grid = Grid::empty();
+        for value in 0..3 {
+            remove(&mut grid, value, [1, 2, 4, 5, 7, 8]);
+        }
+        assert_step(
+            &grid,
+            Technique::HiddenTriple,
+            Deduction::Eliminate(pairs(&[0, 3, 6], &[3, 4, 5, 6, 7, 8])),
+            "hidden triple 1,2,3 in row 1 at r1c1,r1c4,r1c7: r1c1<>4",
+        );
+        let mut grid = Grid::empty();
+        for value in 0..4 {
+            remove(&mut grid, value, [2, 4, 5, 7, 8]);
+        }
+        assert_step(
+            &grid,
+            Technique::HiddenQuad,
+            Deduction::Eliminate(pairs(&[0, 1, 3, 6], &[4, 5, 6, 7, 8])),
+            "hidden quad 1,2,3,4 in row 1 at r1c1,r1c2,r1c4,r1c7: r1c1<>5",
+        );
+    }
+
+    #[test]
+    fn fishes() {
+        // 1 of rows 1 and 5 in columns 1 and 5
+        let mut grid = without(0, [1, 2, 3, 5, 6, 7, 8]);
+        remove(&mut grid, 0, [37, 38, 39, 41, 42, 43, 44]);
+        assert_step(
+            &grid,
+            Technique::XWing,
+            Deduction::Eliminate(pairs(
+                &[9, 18, 27, 45, 54, 63, 72, 13, 22, 31, 49, 58, 67, 76],
+                &[0],
+            )),
+            "X-Wing 1 in rows 1,5 and columns 1,5: r2c1<>1",
+        );
+        // 1 of rows 1, 4 and 7 in columns 1, 4 and 7
+        let mut grid = without(0, [1, 2, 4, 5, 6, 7, 8]);
+        remove(&mut grid, 0, [27, 28, 29, 31, 32, 34, 35]);
+        remove(&mut grid, 0, [55, 56, 57, 58, 59, 61, 62]);
+        assert_step(
+            &grid,
+            Technique::Swordfish,
+            Deduction::Eliminate(pairs(
+                &[
+                    9, 18, 36, 45, 63, 72, 12, 21, 39, 48, 66, 75, 15, 24, 42, 51, 69, 78,
+                ],
+                &[0],
+            )),
+            "Swordfish 1 in rows 1,4,7 and columns 1,4,7: r2c1<>1",
+        );
+    }
+
+    #[test]
+    fn chains() {
+        let grid = with_candidates(&[(0, &[0, 1]), (4, &[0, 2]), (36, &[1, 2])]);
+        assert_step(
+            &grid,
+            Technique::XYWing,
+            Deduction::Eliminate(vec![(40, 2)]),
+            "XY-Wing pivot r1c1 with pincers r1c5,r5c1 on 3: r5c5<>3",
+        );
+        // 1 of column 1 in r1c1 or r5c1, of row 5 in r5c1 or r5c5, of
+        // column 5 in r5c5 or r2c5: r1c1 or r2c5 holds it
+        let mut grid = without(0, [9, 18, 27, 45, 54, 63, 72]);
+        remove(&mut grid, 0, [37, 38, 39, 41, 42, 43, 44]);
+        remove(&mut grid, 0, [4, 22, 31, 49, 58, 67, 76]);
+        assert_step(
+            &grid,
+            Technique::SimpleColoring,
+            Deduction::Eliminate(pairs(&[3, 5, 10, 11], &[0])),
+            "simple coloring 1 on r1c1,r5c1,r5c5,r2c5, color trap: r1c4<>1",
+        );
+    }
+
+    #[test]
+    fn stops_when_no_technique_applies() {
+        let sudoku = Sudoku::parse(
+            "48.3............71.2.......7.5....6....2..8.............1.76...3.....4......5....",
+        )
+        .unwrap();
+        let solution = Solver::new().solve(&sudoku);
+        let solution = solution.solution().unwrap().grid();
+
+        let logical = solve_logically(&sudoku);
+        assert!(!logical.is_solved());
+        assert_eq!(logical.steps.len(), 42);
+        assert_eq!(logical.hardest_technique(), Some(Technique::XYWing));
+        // the steps taken are right, and leave the grid stuck but sound
+        let grid = logical.sudoku.grid();
+        for index in 0..81 {
+            let value = solution.value(index).unwrap();
+            match grid.value(index) {
+                Some(placed) => assert_eq!(placed, value),
+                None => assert!(grid.candidates(index).contains(value)),
+            }
+        }
+        assert!(Technique::ALL
+            .iter()
+            .all(|technique| technique.apply(grid).is_none()));
+    }
+}
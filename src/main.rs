@@ -1,501 +1,1728 @@
 extern crate num_cpus;
 use std::io::{self, Write};
-use std::sync::RwLock;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 extern crate clap;
-use clap::{Parser, ValueEnum};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[macro_use]
 extern crate error_chain;
 
+extern crate glob;
+extern crate rand;
 extern crate rayon;
+extern crate serde;
+extern crate serde_json;
 
 use rayon::prelude::*;
-
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-
-use std::time::Instant;
-
-mod constants;
-use crate::constants::ADJACENT_CELLS;
-use crate::constants::ADJACENT_VALUES;
+use rayon::ThreadPool;
+use serde::{Serialize, Serializer};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::sync::{Arc, Mutex};
+
+extern crate sudoku_solver;
+use sudoku_solver::bench::{bench_set, change_percent, Baseline, BenchOptions};
+use sudoku_solver::formats::{
+    comment_metadata, commented, metadata_comment, metadata_text, parse_sudoku, split_cage_texts,
+    split_layout, text_position, write_sudoku, FileFormat, GridStrings, DETECTION_LINES,
+};
+use sudoku_solver::geometry;
+use sudoku_solver::killer::{cages_to_text, check_cages, generate_killer, parse_cages};
+use sudoku_solver::{
+    generate, rate, solve_logically, Board, BoxShape, Conflict, Engine, GeneratorOptions, Geometry,
+    Outcome, ParseError, ParseErrorKind, SolutionCount, SolveResult, SolveStats, Solver,
+    SolverConfig, Sudoku, Symbols, Symmetry,
+};
+
+use std::time::{Duration, Instant};
 
 error_chain! {
     foreign_links {
         Io(std::io::Error);
+        Json(serde_json::Error);
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
-enum CellValue {
-    Value(usize),
-    Possibilities([bool; 9]),
+/// A grid the solving commands work on: a classic sudoku, or a board of
+/// another size.
+trait Solvable: fmt::Display + Sized + Send + Sync + 'static {
+    fn solve(&self, solver: &Solver) -> SolveResult<Self>;
+    fn count_solutions(&self, solver: &Solver, limit: usize) -> SolutionCount<Self>;
+    fn conflicts(&self) -> Vec<Conflict>;
+    fn is_complete(&self) -> bool;
+    fn to_line(&self) -> String;
+    fn to_string_with_possibilities(&self) -> String;
+    /// The grid as a classic sudoku, the only ones explained with human
+    /// techniques.
+    fn sudoku(&self) -> Option<&Sudoku>;
 }
 
-type Grid = [CellValue; 81];
+impl Solvable for Sudoku {
+    fn solve(&self, solver: &Solver) -> SolveResult {
+        solver.solve(self)
+    }
 
-impl CellValue {
-    pub fn is_value(&self) -> bool {
-        match *self {
-            CellValue::Value(_) => true,
-            CellValue::Possibilities(_) => false,
-        }
+    fn count_solutions(&self, solver: &Solver, limit: usize) -> SolutionCount {
+        solver.count_solutions(self, limit)
     }
 
-    pub fn get_nb_possibility(&self) -> usize {
-        match *self {
-            CellValue::Value(_) => 10,
-            CellValue::Possibilities(values) => {
-                values
-                    .iter()
-                    .fold(0, |acc, &p| if p { acc + 1 } else { acc })
-            }
-        }
+    fn conflicts(&self) -> Vec<Conflict> {
+        Sudoku::conflicts(self)
+    }
+
+    fn is_complete(&self) -> bool {
+        Sudoku::is_complete(self)
+    }
+
+    fn to_line(&self) -> String {
+        Sudoku::to_line(self)
+    }
+
+    fn to_string_with_possibilities(&self) -> String {
+        Sudoku::to_string_with_possibilities(self)
+    }
+
+    fn sudoku(&self) -> Option<&Sudoku> {
+        Some(self)
     }
 }
 
-fn is_grid_complete_full(g: Grid) -> bool {
-    g.iter()
-        .enumerate()
-        .all(|x| x.1.is_value() && check_grid_at(g, x.0))
+impl Solvable for Board {
+    fn solve(&self, solver: &Solver) -> SolveResult<Board> {
+        solver.solve_board(self)
+    }
+
+    fn count_solutions(&self, solver: &Solver, limit: usize) -> SolutionCount<Board> {
+        solver.count_board_solutions(self, limit)
+    }
+
+    fn conflicts(&self) -> Vec<Conflict> {
+        Board::conflicts(self)
+    }
+
+    fn is_complete(&self) -> bool {
+        Board::is_complete(self)
+    }
+
+    fn to_line(&self) -> String {
+        Board::to_line(self)
+    }
+
+    fn to_string_with_possibilities(&self) -> String {
+        Board::to_string_with_possibilities(self)
+    }
+
+    fn sudoku(&self) -> Option<&Sudoku> {
+        None
+    }
 }
 
-fn print_grid(g: Grid) {
-    print_grid_option(g, false);
+fn treat_grid<S: Solvable, W: Write>(
+    solver: &Solver,
+    puzzle: &Puzzle<S>,
+    verbose: bool,
+    pencil_marks: bool,
+    stats: bool,
+    handle: &mut W,
+) {
+    let sudoku = &puzzle.grid;
+    let result = sudoku.solve(solver);
+    let duration = result.elapsed;
+
+    match result.outcome {
+        Outcome::Solved(ref solution) => {
+            let _ = writeln!(
+                handle,
+                "{}: Grid complete ! in {} us{}",
+                puzzle.source,
+                duration.as_micros(),
+                puzzle.comment()
+            );
+            if stats {
+                let _ = writeln!(handle, "  {}", result.stats);
+            }
+            if verbose {
+                let _ = write!(
+                    handle,
+                    "{}{}",
+                    grid_text(sudoku, pencil_marks),
+                    grid_text(solution.as_ref(), pencil_marks)
+                );
+            }
+            if !solution.is_complete() {
+                let _ = writeln!(handle, "Grid is not correct!");
+            }
+        }
+        Outcome::Unsolvable => {
+            let _ = writeln!(
+                handle,
+                "{}: Couldn't solve the sudoku :( in {} us{}",
+                puzzle.source,
+                duration.as_micros(),
+                puzzle.comment()
+            );
+            if stats {
+                let _ = writeln!(handle, "  {}", result.stats);
+            }
+            if verbose {
+                let _ = write!(handle, "{}", grid_text(sudoku, pencil_marks));
+            }
+        }
+        Outcome::Invalid(conflicts) => {
+            let _ = writeln!(
+                handle,
+                "{}: Invalid grid: {}{}",
+                puzzle.source,
+                conflicts_description(&conflicts),
+                puzzle.comment()
+            );
+            if verbose {
+                let _ = write!(handle, "{}", grid_text(sudoku, pencil_marks));
+            }
+        }
+        Outcome::Interrupted(_) => {
+            let _ = writeln!(
+                handle,
+                "{}: Timed out after {} ms{}",
+                puzzle.source,
+                duration.as_millis(),
+                puzzle.comment()
+            );
+            if stats {
+                let _ = writeln!(handle, "  {}", result.stats);
+            }
+            if verbose {
+                let _ = write!(handle, "{}", grid_text(sudoku, pencil_marks));
+            }
+        }
+    }
 }
 
-fn print_grid_option(g: Grid, with_possibilities: bool) {
-    let mut cnt = 0;
-    let mut line = 0;
+fn conflicts_description(conflicts: &[Conflict]) -> String {
+    conflicts
+        .iter()
+        .map(Conflict::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    let mut output = String::new();
+fn count_grid<S: Solvable, W: Write>(
+    solver: &Solver,
+    puzzle: &Puzzle<S>,
+    limit: usize,
+    verbose: bool,
+    pencil_marks: bool,
+    stats: bool,
+    handle: &mut W,
+) {
+    let sudoku = &puzzle.grid;
+    let now = Instant::now();
+    let count = sudoku.count_solutions(solver, limit);
+    let duration = now.elapsed();
 
-    for &x in g.iter() {
-        cnt += 1;
+    let _ = writeln!(
+        handle,
+        "{}: {} solution(s) in {} us{}{}",
+        puzzle.source,
+        count,
+        duration.as_micros(),
+        if count.interrupted.is_some() {
+            ", timed out"
+        } else {
+            ""
+        },
+        puzzle.comment()
+    );
+    if stats {
+        let _ = writeln!(handle, "  {}", count.stats);
+    }
+    if verbose {
+        let _ = write!(handle, "{}", grid_text(sudoku, pencil_marks));
+        for solution in &count.solutions {
+            let _ = write!(handle, "{}", grid_text(solution, pencil_marks));
+        }
+    }
+}
 
-        match x {
-            CellValue::Value(i) => output.push_str(&(i + 1).to_string()),
-            CellValue::Possibilities(p) => {
-                if with_possibilities {
-                    output.push('(');
-                    for (idx, &val) in p.iter().enumerate() {
-                        if val {
-                            output.push_str(&(idx + 1).to_string());
-                        }
-                    }
-                    output.push(')');
-                } else {
-                    output.push('_');
-                }
+/// The grids `--verbose` prints, without the status lines, so that they can
+/// be read back by `-f pencil-marks`: the grid with its metadata, then its
+/// solution, or the solutions found up to `limit` when counting.
+fn pencil_mark_grids<S: Solvable, W: Write>(
+    solver: &Solver,
+    puzzle: &Puzzle<S>,
+    limit: Option<usize>,
+    handle: &mut W,
+) {
+    let grid = puzzle.grid.to_string_with_possibilities();
+    let _ = write!(handle, "{}", commented(&grid, &puzzle.comment()));
+    match limit {
+        Some(limit) => {
+            for solution in &puzzle.grid.count_solutions(solver, limit).solutions {
+                let _ = write!(handle, "{}", solution.to_string_with_possibilities());
             }
         }
-
-        if cnt == 9 {
-            line += 1;
-            output.push('\n');
-            cnt = 0;
-            if line == 3 {
-                line = 0;
-                output.push('\n');
+        None => {
+            if let Some(solution) = puzzle.grid.solve(solver).solution() {
+                let _ = write!(handle, "{}", solution.to_string_with_possibilities());
             }
-        } else if cnt % 3 == 0 {
-            output.push_str("   ");
-        } else {
-            output.push(' ');
         }
     }
-    print!("{}", output);
 }
 
-/*
-fn get_line(index: usize) -> usize {
-    (index / 9) * 9
+/// A grid as printed by `--verbose`, showing the candidates of the empty
+/// cells with `--pencil-marks`.
+fn grid_text<S: Solvable>(sudoku: &S, pencil_marks: bool) -> String {
+    if pencil_marks {
+        sudoku.to_string_with_possibilities()
+    } else {
+        sudoku.to_string()
+    }
 }
 
-fn get_column(index: usize) -> usize {
-    index % 9
+/// How a puzzle ended, in the machine-readable outputs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Solved,
+    Unsolvable,
+    Invalid,
+    /// More than one solution, only told apart when counting.
+    Multiple,
+    /// Stopped by `--timeout-ms`.
+    Timeout,
 }
 
-fn get_head_of_block(index: usize) -> usize {
-    index - (index % 3) - (index / 9 % 3) * 9
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Status::Solved => "solved",
+            Status::Unsolvable => "unsolvable",
+            Status::Invalid => "invalid",
+            Status::Multiple => "multiple",
+            Status::Timeout => "timeout",
+        })
+    }
 }
-*/
 
-fn check_grid_at(g: Grid, index: usize) -> bool {
-    let adj_cells = ADJACENT_CELLS[index];
-    //let line = get_line(index);
+/// Result of one puzzle in the machine-readable outputs.
+struct Record {
+    source: Source,
+    input: String,
+    solution: Option<String>,
+    status: Status,
+    elapsed: Duration,
+    stats: SolveStats,
+    metadata: Vec<(String, String)>,
+}
 
-    if !check_no_redundant_value(g, adj_cells[0]) {
-        return false;
-    }
-    if !check_no_redundant_value(g, adj_cells[1]) {
-        return false;
+impl Record {
+    fn solved<S: Solvable>(solver: &Solver, puzzle: &Puzzle<S>) -> Record {
+        let result = puzzle.grid.solve(solver);
+        let status = match result.outcome {
+            Outcome::Solved(_) => Status::Solved,
+            Outcome::Unsolvable => Status::Unsolvable,
+            Outcome::Invalid(_) => Status::Invalid,
+            Outcome::Interrupted(_) => Status::Timeout,
+        };
+        Record {
+            source: puzzle.source.clone(),
+            input: puzzle.grid.to_line(),
+            solution: result.solution().map(S::to_line),
+            status,
+            elapsed: result.elapsed,
+            stats: result.stats,
+            metadata: puzzle.metadata.clone(),
+        }
     }
-    if !check_no_redundant_value(g, adj_cells[2]) {
-        return false;
+
+    fn counted<S: Solvable>(solver: &Solver, puzzle: &Puzzle<S>, limit: usize) -> Record {
+        let now = Instant::now();
+        let count = puzzle.grid.count_solutions(solver, limit);
+        let elapsed = now.elapsed();
+
+        // two solutions found before the timeout are enough to tell
+        let status = match count.count {
+            0 if !puzzle.grid.conflicts().is_empty() => Status::Invalid,
+            0 | 1 if count.interrupted.is_some() => Status::Timeout,
+            0 => Status::Unsolvable,
+            1 => Status::Solved,
+            _ => Status::Multiple,
+        };
+        Record {
+            source: puzzle.source.clone(),
+            input: puzzle.grid.to_line(),
+            solution: count.solutions.first().map(S::to_line),
+            status,
+            elapsed,
+            stats: count.stats,
+            metadata: puzzle.metadata.clone(),
+        }
     }
+}
 
-    true
+/// Writes records as a JSON array, JSON Lines or CSV.
+struct RecordWriter<W: Write> {
+    format: OutputFormat,
+    /// Whether the records hold all the statistics or only the guesses.
+    stats: bool,
+    out: W,
+    written: usize,
 }
 
-fn check_no_redundant_value(grid: Grid, val: [usize; 8]) -> bool {
-    let mut checked: [bool; 9] = [false; 9];
-    for &v in &val {
-        if let CellValue::Value(cell_value) = grid[v] {
-            if checked[cell_value] {
-                return false;
+const CSV_HEADER: &str = "file,line,input,solution,status,elapsed_us,guesses";
+
+const CSV_STATS_HEADER: &str = ",nodes,dead_ends,max_depth,propagated";
+
+const CSV_METADATA_HEADER: &str = ",metadata";
+
+impl<W: Write> RecordWriter<W> {
+    fn new(format: OutputFormat, stats: bool, mut out: W) -> io::Result<RecordWriter<W>> {
+        match format {
+            OutputFormat::Json => writeln!(out, "[")?,
+            OutputFormat::Csv if stats => writeln!(
+                out,
+                "{}{}{}",
+                CSV_HEADER, CSV_STATS_HEADER, CSV_METADATA_HEADER
+            )?,
+            OutputFormat::Csv => writeln!(out, "{}{}", CSV_HEADER, CSV_METADATA_HEADER)?,
+            OutputFormat::Text | OutputFormat::Jsonl => {}
+        }
+        Ok(RecordWriter {
+            format,
+            stats,
+            out,
+            written: 0,
+        })
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => {
+                if self.written > 0 {
+                    writeln!(self.out, ",")?;
+                }
+                write!(self.out, "  {}", json_record(record, self.stats))?;
             }
-            checked[cell_value] = true;
-        }
-    }
-    true
-}
-
-fn get_adjacent_cells(index: usize) -> [usize; 20] {
-    // let column = get_column(index);
-    // let head_of_line = get_line(index);
-    // let head_of_block = get_head_of_block(index);
-    // [column,
-    // column + 9,
-    // column + 18,
-    // column + 27,
-    // column + 36,
-    // column + 45,
-    // column + 54,
-    // column + 63,
-    // column + 72,
-    // head_of_line,
-    // head_of_line + 1,
-    // head_of_line + 2,
-    // head_of_line + 3,
-    // head_of_line + 4,
-    // head_of_line + 5,
-    // head_of_line + 6,
-    // head_of_line + 7,
-    // head_of_line + 8,
-    // head_of_block,
-    // head_of_block + 1,
-    // head_of_block + 2,
-    // head_of_block + 9,
-    // head_of_block + 10,
-    // head_of_block + 11,
-    // head_of_block + 18,
-    // head_of_block + 19,
-    // head_of_block + 20]
-    //
-    ADJACENT_VALUES[index]
-}
-
-fn build_possible_values_grid(grid: &mut Grid) -> bool {
-    for index in 0..81 {
-        if !grid[index].is_value() {
-            let possible_value = get_cell_value(*grid, index);
-            if let CellValue::Possibilities(poss) = possible_value {
-                match possible_value.get_nb_possibility() {
-                    0 => {
-                        return false;
-                    }
-                    1 => {
-                        if !set_cell_value_at(grid, index, get_last_value_possible(poss)) {
-                            return false;
-                        }
-                    }
-                    _ => {
-                        grid[index] = possible_value;
-                    }
+            OutputFormat::Jsonl => writeln!(self.out, "{}", json_record(record, self.stats))?,
+            OutputFormat::Csv => {
+                write!(
+                    self.out,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&record.source.name),
+                    record.source.line,
+                    record.input,
+                    record.solution.as_deref().unwrap_or(""),
+                    record.status,
+                    record.elapsed.as_micros(),
+                    record.stats.guesses
+                )?;
+                if self.stats {
+                    let stats = &record.stats;
+                    write!(
+                        self.out,
+                        ",{},{},{},{}",
+                        stats.nodes, stats.dead_ends, stats.max_depth, stats.propagated
+                    )?;
                 }
+                writeln!(self.out, ",{}", csv_field(&metadata_text(&record.metadata)))?;
             }
+            OutputFormat::Text => unreachable!("text output does not use records"),
         }
+        self.written += 1;
+        Ok(())
     }
-    true
-}
-
-fn get_cell_value(grid: Grid, index: usize) -> CellValue {
-    let mut possible_values = [true; 9];
 
-    for &val in &get_adjacent_cells(index) {
-        if let CellValue::Value(num) = grid[val] {
-            if possible_values[num] {
-                possible_values[num] = false;
+    fn finish(mut self) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            if self.written > 0 {
+                writeln!(self.out)?;
             }
+            writeln!(self.out, "]")?;
         }
+        self.out.flush()
     }
+}
+
+/// A record as written in the JSON outputs.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    file: &'a str,
+    line: usize,
+    input: &'a str,
+    solution: Option<&'a str>,
+    status: Status,
+    elapsed_us: u128,
+    guesses: usize,
+    #[serde(flatten)]
+    stats: Option<JsonStats>,
+    #[serde(serialize_with = "serialize_metadata")]
+    metadata: &'a [(String, String)],
+}
+
+/// The statistics written with `--stats`, besides the guesses.
+#[derive(Serialize)]
+struct JsonStats {
+    nodes: usize,
+    dead_ends: usize,
+    max_depth: usize,
+    propagated: usize,
+}
+
+/// Writes the metadata as an object, keeping the order of its keys.
+fn serialize_metadata<S: Serializer>(
+    metadata: &&[(String, String)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(metadata.iter().map(|(key, value)| (key, value)))
+}
 
-    CellValue::Possibilities(possible_values)
+fn json_record(record: &Record, stats: bool) -> String {
+    let json = JsonRecord {
+        file: &record.source.name,
+        line: record.source.line,
+        input: &record.input,
+        solution: record.solution.as_deref(),
+        status: record.status,
+        elapsed_us: record.elapsed.as_micros(),
+        guesses: record.stats.guesses,
+        stats: stats.then_some(JsonStats {
+            nodes: record.stats.nodes,
+            dead_ends: record.stats.dead_ends,
+            max_depth: record.stats.max_depth,
+            propagated: record.stats.propagated,
+        }),
+        metadata: &record.metadata,
+    };
+    // a record of strings and numbers always serializes
+    serde_json::to_string(&json).unwrap()
 }
 
-fn get_last_value_possible(possible_values: [bool; 9]) -> usize {
-    // There is only one option left
-    match possible_values.iter().enumerate().find(|v| *v.1) {
-        // error case, should never happen
-        None => 11,
-        Some((idx, _)) => idx,
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
     }
 }
 
-fn fill_one_possibility_cells(grid: &mut Grid, values: [usize; 20]) -> bool {
-    for &val in &values {
-        if let CellValue::Possibilities(possible_values) = grid[val] {
-            match possible_values {
-                [false, false, false, false, false, false, false, false, false] => {
-                    return false;
-                }
-                [true, false, false, false, false, false, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 0) {
-                        return false;
-                    }
-                }
-                [false, true, false, false, false, false, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 1) {
-                        return false;
-                    }
-                }
-                [false, false, true, false, false, false, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 2) {
-                        return false;
-                    }
-                }
-                [false, false, false, true, false, false, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 3) {
-                        return false;
-                    }
-                }
-                [false, false, false, false, true, false, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 4) {
-                        return false;
-                    }
-                }
-                [false, false, false, false, false, true, false, false, false] => {
-                    if !set_cell_value_at(grid, val, 5) {
-                        return false;
-                    }
-                }
-                [false, false, false, false, false, false, true, false, false] => {
-                    if !set_cell_value_at(grid, val, 6) {
-                        return false;
-                    }
-                }
-                [false, false, false, false, false, false, false, true, false] => {
-                    if !set_cell_value_at(grid, val, 7) {
-                        return false;
-                    }
-                }
-                [false, false, false, false, false, false, false, false, true] => {
-                    if !set_cell_value_at(grid, val, 8) {
-                        return false;
-                    }
-                }
-                _ => {}
-            }
+fn explain_grid<W: Write>(source: &Source, sudoku: &Sudoku, comment: &str, handle: &mut W) {
+    let solution = solve_logically(sudoku);
 
-            /*
-            match grid[val].get_nb_possibility() {
-                0 => {
-                    return false;
-                }
-                1 => {
-                    if !set_cell_value_at(grid, val, get_last_value_possible(possible_values)) {
-                        return false;
-                    }
-                }
-                _ => {}
-            }
-            */
-        }
+    let _ = writeln!(handle, "{}: {}{}", source, sudoku.to_line(), comment);
+    for (i, step) in solution.steps.iter().enumerate() {
+        let _ = writeln!(handle, "{:4}. {}", i + 1, step);
+    }
+    if solution.is_solved() {
+        let _ = writeln!(handle, "Solved by logic in {} steps", solution.steps.len());
+        let _ = write!(handle, "{}", solution.sudoku);
+    } else {
+        let _ = writeln!(
+            handle,
+            "No logical step left after {} steps, the rest needs guessing",
+            solution.steps.len()
+        );
+        let _ = write!(handle, "{}", solution.sudoku.to_string_with_possibilities());
     }
-    true
 }
 
-fn set_cell_value_at(grid: &mut Grid, index: usize, cell_value: usize) -> bool {
-    grid[index] = CellValue::Value(cell_value);
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Opt {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    let adjs = get_adjacent_cells(index);
+    #[command(flatten)]
+    input: InputOpt,
 
-    adjs.iter().for_each(|val| {
-        if let CellValue::Possibilities(ref mut possible_values) = grid[*val] {
-            if possible_values[cell_value] {
-                possible_values[cell_value] = false;
-            }
+    /// Number of threads, the number of CPUs by default
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    /// What runs in parallel: several puzzles at once, the branches of a
+    /// guess, both, or nothing
+    #[arg(long, value_enum, default_value_t = Parallel::Both)]
+    parallel: Parallel,
+
+    /// Print the search statistics of each grid: nodes, guesses, dead ends,
+    /// depth and cells filled by propagation
+    #[arg(long, conflicts_with = "explain")]
+    stats: bool,
+
+    /// Give up on a grid after this many milliseconds
+    #[arg(long, value_name = "MS")]
+    timeout_ms: Option<u64>,
+
+    /// Always give the lexicographically smallest solution, guessing in a
+    /// fixed order on a single thread per puzzle
+    #[arg(long)]
+    deterministic: bool,
+
+    #[arg(name = "verbose", short, long)]
+    verbose: bool,
+
+    /// Print the grids with the candidates of their empty cells, in the
+    /// notation read back by `-f pencil-marks`: only each grid and its
+    /// solutions, or with the status lines when --verbose
+    #[arg(long)]
+    pencil_marks: bool,
+
+    /// Count the solutions of each grid, up to --limit, instead of solving it
+    #[arg(long)]
+    count: bool,
+
+    /// Check that each grid has exactly one solution (same as --count --limit 2)
+    #[arg(long, conflicts_with = "count")]
+    unique: bool,
+
+    /// Solve each grid with human techniques, printing every deduction
+    #[arg(long, conflicts_with_all = ["count", "unique", "size", "box_shape", "jigsaw", "diagonal", "hyper", "killer"])]
+    explain: bool,
+
+    /// Number of rows, columns and values of the grids, 9 by default. The
+    /// boxes are as square as possible, 2x3 for 6 and 3x4 for 12
+    #[arg(long, value_name = "N", conflicts_with = "box_shape")]
+    size: Option<usize>,
+
+    /// Shape of the boxes, such as 2x3 or 4x4, for grids of their size
+    #[arg(long = "box", value_name = "ROWSxCOLUMNS")]
+    box_shape: Option<BoxShape>,
+
+    /// Solve jigsaw sudokus, whose boxes are irregular regions. Each grid is
+    /// followed by the layout of its regions, a character per cell naming
+    /// its region, on the same line or on as many lines as the grid
+    #[arg(long, conflicts_with = "box_shape")]
+    jigsaw: bool,
+
+    /// Solve Sudoku-X grids, whose two main diagonals also hold every value
+    /// once
+    #[arg(long)]
+    diagonal: bool,
+
+    /// Solve Hyper sudokus (Windoku), whose windows set one cell apart from
+    /// the boxes and the edges also hold every value once
+    #[arg(long, conflicts_with = "jigsaw")]
+    hyper: bool,
+
+    /// Solve killer sudokus, whose cages add up to sums. Each FILE holds
+    /// the cages of puzzles separated by blank lines, a cage per line as its
+    /// sum then its cells such as `15 r1c1 r1c2`, or of one puzzle in JSON
+    #[arg(long, conflicts_with = "jigsaw")]
+    killer: bool,
+
+    /// Symbols of the values of grids other than 9x9: digits (1-9 then
+    /// letters), hex (0-9 then letters), letters, or numbers separated by
+    /// spaces. Detected from each grid by default
+    #[arg(long)]
+    symbols: Option<Symbols>,
+
+    /// Maximum number of solutions to look for when counting
+    #[arg(long, default_value_t = 1000)]
+    limit: usize,
+
+    /// Solving algorithm: propagation or dlx (Dancing Links)
+    #[arg(long, default_value_t = Engine::Propagation)]
+    engine: Engine,
+
+    /// Print the results as soon as they are found instead of in input order
+    #[arg(long)]
+    unordered: bool,
+
+    /// Print one record per grid with its input, solution, status, time and
+    /// guesses
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with_all = ["explain", "verbose"])]
+    output: OutputFormat,
+}
+
+impl Opt {
+    /// The box shape of the grids given with --size or --box, `None` for
+    /// classic sudokus without extra regions.
+    fn board_shape(&self) -> Result<Option<BoxShape>> {
+        let shape = match (self.size, self.box_shape) {
+            (Some(size), _) => match BoxShape::for_size(size) {
+                Some(shape) => shape,
+                None => bail!(
+                    "no box shape for grids of size {}, give one with --box",
+                    size
+                ),
+            },
+            (None, Some(shape)) => shape,
+            (None, None) => BoxShape::CLASSIC,
+        };
+        let digits = self
+            .symbols
+            .is_none_or(|symbols| symbols == Symbols::Digits);
+        if shape == BoxShape::CLASSIC && digits && !self.diagonal && !self.hyper {
+            Ok(None)
+        } else {
+            Ok(Some(shape))
         }
-    });
+    }
+
+    /// The regions added by --diagonal and --hyper to grids of `size` rows,
+    /// with boxes of `shape` unless they are jigsaws.
+    fn extra_regions(&self, size: usize, shape: Option<BoxShape>) -> Vec<Vec<usize>> {
+        let mut regions = vec![];
+        if self.diagonal {
+            regions.extend(geometry::diagonals(size));
+        }
+        if let Some(shape) = shape.filter(|_| self.hyper) {
+            regions.extend(geometry::windows(shape));
+        }
+        regions
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate puzzles with a unique solution, one per line
+    Generate(GenerateOpt),
+    /// Rate the difficulty of each grid on the Sudoku Explainer scale
+    Rate(RateOpt),
+    /// Check that the givens of each grid do not contradict each other
+    Validate(ValidateOpt),
+    /// Time the solver over sets of puzzles, optionally against a baseline
+    Bench(BenchOpt),
+    /// Write the grids in another file format
+    Convert(ConvertOpt),
+}
 
-    fill_one_possibility_cells(grid, adjs)
+/// The puzzle sets shipped with the solver, benchmarked by default.
+const BENCH_SETS: [&str; 4] = ["top95.txt", "top87.txt", "hardest_20.txt", "grids.txt"];
+
+#[derive(Args, Debug)]
+struct BenchOpt {
+    /// Files of puzzles, each one a set. top95.txt, top87.txt, hardest_20.txt
+    /// and grids.txt by default
+    #[arg(name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Format of the files, detected from their extension or content by
+    /// default
+    #[arg(name = "input format", short = 'f', value_parser = file_format_parser())]
+    input_format: Option<FileFormat>,
+
+    /// Timed runs over each set
+    #[arg(long, default_value_t = BenchOptions::default().runs)]
+    runs: usize,
+
+    /// Untimed runs over each set before the timed ones
+    #[arg(long, default_value_t = BenchOptions::default().warmup)]
+    warmup: usize,
+
+    /// Solving algorithm: propagation or dlx (Dancing Links)
+    #[arg(long, default_value_t = Engine::Propagation)]
+    engine: Engine,
+
+    /// Write the timings to this JSON file, to compare later runs with
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+
+    /// Compare the timings with a file written by --save, failing if a set
+    /// or puzzle got slower
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// Slowdown of the median, in percent, reported as a regression
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    threshold: f64,
 }
 
-fn solve_grid(mut grid: Grid) -> Option<Grid> {
-    if !build_possible_values_grid(&mut grid) {
-        return None;
+#[derive(Args, Debug)]
+struct ValidateOpt {
+    #[command(flatten)]
+    input: InputOpt,
+
+    /// Check the cages of killer sudokus instead of grids: that they cover
+    /// the grid once and can reach their sums
+    #[arg(long)]
+    killer: bool,
+
+    /// Number of rows of the killer grids
+    #[arg(long, value_name = "N", default_value_t = 9, requires = "killer")]
+    size: usize,
+}
+
+#[derive(Args, Debug)]
+struct ConvertOpt {
+    #[command(flatten)]
+    input: InputOpt,
+
+    /// Format to write the grids in
+    #[arg(long, value_parser = file_format_parser())]
+    to: FileFormat,
+}
+
+#[derive(Args, Debug)]
+struct RateOpt {
+    #[command(flatten)]
+    input: InputOpt,
+}
+
+#[derive(Args, Debug)]
+struct InputOpt {
+    /// Files to process, `-` for stdin. Directories stand for the files
+    /// they contain, and quoted glob patterns are expanded
+    #[arg(name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+
+    /// Format of the files, detected from their extension or content by
+    /// default
+    #[arg(name = "input format", short = 'f', value_parser = file_format_parser())]
+    input_format: Option<FileFormat>,
+
+    /// What to do with a malformed grid: skip it with a warning, or stop
+    #[arg(long, value_enum, default_value_t = OnError::Skip)]
+    on_error: OnError,
+}
+
+#[derive(Args, Debug)]
+struct GenerateOpt {
+    /// Number of puzzles to generate
+    #[arg(short = 'n', long, default_value_t = 1)]
+    count: u64,
+
+    /// Seed of the first puzzle, the following ones use the next seeds
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Stop removing clues once the puzzle is down to this count
+    #[arg(long, default_value_t = 17)]
+    min_clues: usize,
+
+    /// Symmetry of the clues: none, rotational, diagonal or mirror
+    #[arg(long, default_value_t = Symmetry::None)]
+    symmetry: Symmetry,
+
+    /// Generate killer sudokus without givens, printing the cages of each
+    /// one a cage per line, followed by a blank line
+    #[arg(long, conflicts_with_all = ["min_clues", "symmetry"])]
+    killer: bool,
+
+    /// Largest number of cells of a killer cage
+    #[arg(
+        long,
+        default_value_t = 4,
+        requires = "killer",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_cage: usize,
+}
+
+/// Parses a file format from its name, listing the formats in the help.
+fn file_format_parser() -> impl TypedValueParser<Value = FileFormat> {
+    let values =
+        FileFormat::ALL.map(|format| PossibleValue::new(format.name()).help(format.description()));
+    PossibleValuesParser::new(values).map(|name| name.parse::<FileFormat>().unwrap())
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum Parallel {
+    Puzzles,
+    Branches,
+    Both,
+    None,
+}
+
+impl Parallel {
+    fn puzzles(self) -> bool {
+        self == Parallel::Puzzles || self == Parallel::Both
     }
 
-    let g: Option<Grid> = None;
-    let counter = RwLock::new(g);
+    fn branches(self) -> bool {
+        self == Parallel::Branches || self == Parallel::Both
+    }
+}
 
-    solve_grid_recurse(grid, &counter)
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Sentences for people
+    Text,
+    /// A JSON array of records
+    Json,
+    /// One JSON record per line
+    Jsonl,
+    /// Comma-separated values, with a header line
+    Csv,
 }
 
-fn solve_grid_recurse(grid: Grid, counter: &RwLock<Option<Grid>>) -> Option<Grid> {
-    // start by the number with the lowest possible values already in the grid when guessing
-    let res = grid
-        .iter()
-        .enumerate()
-        .filter(|t: &(usize, &CellValue)| !t.1.is_value())
-        .min_by_key(|val| val.1.get_nb_possibility());
-
-    if let Some((index, &CellValue::Possibilities(poss))) = res {
-        // parallel loop
-        poss.par_iter()
-            .enumerate()
-            .filter(|t: &(usize, &bool)| *t.1)
-            .for_each(|t: (usize, &bool)| {
-                let (cell_value, _) = t;
-                if counter.read().unwrap().is_none() {
-                    let mut new_g = grid;
-                    if set_cell_value_at(&mut new_g, index, cell_value)
-                        && counter.read().unwrap().is_none()
-                    {
-                        if let Some(gx) = solve_grid_recurse(new_g, counter) {
-                            let mut gres = counter.write().unwrap();
-                            *gres = Some(gx);
-                        }
-                    }
-                }
-            });
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    Skip,
+    Abort,
+}
 
-        return *counter.read().unwrap();
+fn generate_puzzles(opt: &GenerateOpt) {
+    let first_seed = opt.seed.unwrap_or_else(rand::random);
+    if opt.killer {
+        return generate_killers(opt, first_seed);
     }
 
-    Some(grid)
+    let puzzles = (0..opt.count)
+        .into_par_iter()
+        .map(|i| {
+            generate(&GeneratorOptions {
+                seed: first_seed.wrapping_add(i),
+                min_clues: opt.min_clues,
+                symmetry: opt.symmetry,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for puzzle in puzzles {
+        let _ = writeln!(handle, "{}", puzzle.to_line());
+    }
 }
 
-fn parse_grid(grid_string: &str) -> Grid {
-    let mut grid = [CellValue::Possibilities([true; 9]); 81];
+fn generate_killers(opt: &GenerateOpt, first_seed: u64) {
+    let killers = (0..opt.count)
+        .into_par_iter()
+        .map(|i| generate_killer(first_seed.wrapping_add(i), opt.max_cage))
+        .collect::<Vec<_>>();
 
-    let mut i = 0;
-    for splitted in grid_string.split_whitespace() {
-        for s in splitted.split("") {
-            match s {
-                "" => {}
-                "_" | "." => {
-                    i += 1;
-                }
-                val => {
-                    grid[i] = CellValue::Value(val.parse::<usize>().unwrap() - 1);
-                    i += 1;
-                }
-            }
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for (i, cages) in killers.iter().enumerate() {
+        if i > 0 {
+            let _ = writeln!(handle);
         }
+        let _ = write!(handle, "{}", cages_to_text(cages, 9));
     }
-
-    grid
 }
 
-fn treat_grid(grid_string: &str, verbose: bool) {
-    let grid: Grid = parse_grid(grid_string);
+fn rate_puzzles(opt: &RateOpt) -> Result<()> {
+    let puzzles = load_puzzles(&opt.input)?;
 
-    let now = Instant::now();
-    let new_grid = solve_grid(grid);
-    let duration = now.elapsed();
+    let ratings = puzzles
+        .par_iter()
+        .map(|puzzle| rate(&puzzle.grid))
+        .collect::<Vec<_>>();
 
     let stdout = io::stdout();
     let mut handle = stdout.lock();
-
-    match new_grid {
-        Some(new_grid) => {
+    for (puzzle, rating) in puzzles.iter().zip(ratings) {
+        // conflicting givens are reported rather than rated
+        let conflicts = puzzle.grid.conflicts();
+        if !conflicts.is_empty() {
             let _ = writeln!(
                 handle,
-                "Grid complete ! in {} us",
-                (1_000_000 * duration.as_secs() + u64::from(duration.subsec_nanos())) / (1_000)
+                "{}: {} invalid: {}{}",
+                puzzle.source,
+                puzzle.grid.to_line(),
+                conflicts_description(&conflicts),
+                puzzle.comment()
             );
-            if verbose {
-                print_grid(grid);
-                print_grid(new_grid);
+            continue;
+        }
+        match rating {
+            Some(rating) => {
+                let _ = writeln!(
+                    handle,
+                    "{}: {} {:.1} {:<10} {} ({} guesses, {} backtracks){}",
+                    puzzle.source,
+                    puzzle.grid.to_line(),
+                    rating.score,
+                    rating.difficulty,
+                    rating
+                        .hardest_technique
+                        .map_or_else(|| "none".to_string(), |t| t.to_string()),
+                    rating.guesses,
+                    rating.backtracks,
+                    puzzle.comment()
+                );
             }
-            if !is_grid_complete_full(new_grid) {
-                println!("Grid is not correct!");
+            None => {
+                let _ = writeln!(
+                    handle,
+                    "{}: {} has no solution{}",
+                    puzzle.source,
+                    puzzle.grid.to_line(),
+                    puzzle.comment()
+                );
             }
         }
-        None => {
+    }
+    Ok(())
+}
+
+fn validate_puzzles(opt: &ValidateOpt) -> Result<()> {
+    if opt.killer {
+        return validate_killers(opt);
+    }
+    let puzzles = load_puzzles(&opt.input)?;
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+
+    let mut invalid = 0;
+    for puzzle in &puzzles {
+        let conflicts = puzzle.grid.conflicts();
+        if conflicts.is_empty() {
             let _ = writeln!(
                 handle,
-                "Couldn't solve the sudoku :( in {} ms",
-                (1_000_000 * duration.as_secs() + u64::from(duration.subsec_nanos())) / (1_000)
+                "{}: {} valid{}",
+                puzzle.source,
+                puzzle.grid.to_line(),
+                puzzle.comment()
             );
-            if verbose {
-                print_grid(grid);
+        } else {
+            invalid += 1;
+            let _ = writeln!(
+                handle,
+                "{}: {} invalid: {}{}",
+                puzzle.source,
+                puzzle.grid.to_line(),
+                conflicts_description(&conflicts),
+                puzzle.comment()
+            );
+        }
+    }
+
+    if invalid > 0 {
+        bail!("{} of {} grids are invalid", invalid, puzzles.len());
+    }
+    Ok(())
+}
+
+fn validate_killers(opt: &ValidateOpt) -> Result<()> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+
+    let (mut invalid, mut total) = (0, 0);
+    for puzzle in read_cage_texts(&opt.input)? {
+        let puzzle = puzzle?;
+        total += 1;
+        match parse_cages(&puzzle.grid, opt.size).and_then(|cages| check_cages(&cages, opt.size)) {
+            Ok(()) => {
+                let _ = writeln!(handle, "{}: valid{}", puzzle.source, puzzle.comment());
+            }
+            Err(err) => {
+                invalid += 1;
+                let _ = writeln!(
+                    handle,
+                    "{}: invalid: {}{}",
+                    puzzle.source,
+                    err,
+                    puzzle.comment()
+                );
             }
         }
     }
+
+    if invalid > 0 {
+        bail!("{} of {} cage sets are invalid", invalid, total);
+    }
+    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Opt {
-    /// Files to process
-    #[arg(name = "FILE")]
-    file: PathBuf,
+fn bench_puzzles(opt: &BenchOpt) -> Result<()> {
+    let sets = if opt.files.is_empty() {
+        BENCH_SETS
+            .iter()
+            .map(|&path| Input::File(path.into()))
+            .collect()
+    } else {
+        expand_inputs(&opt.files)?
+    };
+    let baseline = match opt.baseline {
+        Some(ref path) => {
+            let file = File::open(path).chain_err(|| format!("cannot read {}", path.display()))?;
+            let baseline: Baseline = serde_json::from_reader(BufReader::new(file))
+                .chain_err(|| format!("invalid baseline {}", path.display()))?;
+            Some(baseline)
+        }
+        None => None,
+    };
 
-    #[arg(name = "input format", value_enum, default_value_t = InputFormat::OneLiner, short='f')]
-    input_format: InputFormat,
+    let solver = Solver::with_engine(opt.engine);
+    let options = BenchOptions {
+        warmup: opt.warmup,
+        runs: opt.runs,
+    };
 
-    #[arg(name = "verbose", short, long)]
-    verbose: bool,
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let mut results = vec![];
+    for input in sets {
+        let puzzles =
+            read_input_puzzles(input, opt.input_format, OnError::Skip, GridKind::sudoku())
+                .map(|puzzle| puzzle.map(|puzzle| (puzzle.source, puzzle.grid)))
+                .collect::<Result<Vec<_>>>()?;
+        let Some((source, _)) = puzzles.first() else {
+            continue;
+        };
+        let name = source.name.clone();
+        let puzzles = puzzles
+            .into_iter()
+            .map(|(source, sudoku)| (source.line, sudoku))
+            .collect::<Vec<_>>();
+
+        let set = bench_set(&solver, &name, &puzzles, &options);
+        writeln!(
+            handle,
+            "{}: {} puzzles, {} runs: {}",
+            set.name,
+            set.puzzles.len(),
+            set.runs,
+            set.total
+        )?;
+        if let Some(base) = baseline
+            .as_ref()
+            .and_then(|baseline| baseline.set(&set.name))
+        {
+            writeln!(
+                handle,
+                "  median {:+.1}% against the baseline",
+                change_percent(base.total.median_us, set.total.median_us)
+            )?;
+        }
+        for puzzle in &set.puzzles {
+            writeln!(handle, "  {}:{}: {}", set.name, puzzle.line, puzzle.timings)?;
+        }
+        results.push(set);
+    }
+
+    let regressions = baseline.map_or_else(Vec::new, |baseline| {
+        baseline.regressions(&results, opt.threshold)
+    });
+    if let Some(ref path) = opt.save {
+        let file = File::create(path).chain_err(|| format!("cannot write {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &Baseline { sets: results })?;
+    }
+
+    if !regressions.is_empty() {
+        for regression in &regressions {
+            writeln!(handle, "Regression: {}", regression)?;
+        }
+        bail!(
+            "{} regressions of more than {}% against the baseline",
+            regressions.len(),
+            opt.threshold
+        );
+    }
+    Ok(())
 }
 
-#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum InputFormat {
-    OneLiner,
-    MultiLine,
+fn convert_puzzles(opt: &ConvertOpt) -> Result<()> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for puzzle in read_puzzles(&opt.input)? {
+        let puzzle = puzzle?;
+        write_sudoku(opt.to, &puzzle.grid, &puzzle.metadata, &mut handle)?;
+    }
+    Ok(())
 }
 
 fn run() -> Result<()> {
     let opt = Opt::parse();
 
-    let mut grid_strings = vec![];
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.threads.unwrap_or_else(num_cpus::get))
+        .build()
+        .chain_err(|| "cannot start the threads")?;
+    let pool = Arc::new(pool);
 
-    let input = File::open(opt.file)?;
-    let buffered = BufReader::new(input);
+    let result = pool.install(|| match opt.command {
+        Some(Command::Generate(ref generate_opt)) => {
+            generate_puzzles(generate_opt);
+            Ok(())
+        }
+        Some(Command::Rate(ref rate_opt)) => rate_puzzles(rate_opt),
+        Some(Command::Validate(ref validate_opt)) => validate_puzzles(validate_opt),
+        Some(Command::Bench(ref bench_opt)) => bench_puzzles(bench_opt),
+        Some(Command::Convert(ref convert_opt)) => convert_puzzles(convert_opt),
+        None => solve_file(&opt, &pool),
+    });
 
-    match opt.input_format {
-        InputFormat::MultiLine => {
-            let not_empty_lines = buffered
-                .lines()
-                .filter_map(|l| {
-                    if let Ok(content) = l {
-                        Some(content)
-                    } else {
-                        None
-                    }
+    match result {
+        // whoever reads the output stopped, like `head` once it has enough lines
+        Err(ref err) if is_broken_pipe(err) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Io(ref err) => err.kind() == io::ErrorKind::BrokenPipe,
+        _ => false,
+    }
+}
+
+/// Where a grid comes from, so that results can be traced back to it.
+#[derive(Clone, Debug)]
+struct Source {
+    /// Path of the file, or `-` for stdin.
+    name: Arc<str>,
+    /// Line the grid starts at, from 1.
+    line: usize,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.name, self.line)
+    }
+}
+
+struct Puzzle<S = Sudoku> {
+    source: Source,
+    grid: S,
+    /// Keys and values given with the grid, such as its author.
+    metadata: Vec<(String, String)>,
+}
+
+impl<S> Puzzle<S> {
+    /// The metadata as a comment to append to a line, empty without any.
+    fn comment(&self) -> String {
+        metadata_comment(&self.metadata)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    fn name(&self) -> String {
+        match *self {
+            Input::Stdin => "-".to_string(),
+            Input::File(ref path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Turns the `FILE` arguments into the list of inputs to read, in order:
+/// `-` is stdin, a directory stands for its files sorted by name, and a
+/// pattern matching no path as is gets expanded as a glob.
+fn expand_inputs(args: &[PathBuf]) -> Result<Vec<Input>> {
+    let mut inputs = vec![];
+
+    for arg in args {
+        if arg.as_os_str() == "-" {
+            if inputs.contains(&Input::Stdin) {
+                bail!("stdin can only be read once");
+            }
+            inputs.push(Input::Stdin);
+        } else if arg.is_dir() {
+            let mut paths = vec![];
+            for entry in fs::read_dir(arg).chain_err(|| format!("cannot read {}", arg.display()))? {
+                let entry = entry?;
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && entry.file_type()?.is_file() {
+                    paths.push(entry.path());
+                }
+            }
+            paths.sort();
+            inputs.extend(paths.into_iter().map(Input::File));
+        } else if !arg.exists() && is_glob(arg) {
+            let pattern = arg.to_string_lossy();
+            let mut paths = vec![];
+            for path in glob::glob(&pattern).chain_err(|| format!("invalid pattern {}", pattern))? {
+                let path = path.chain_err(|| format!("cannot expand {}", pattern))?;
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+            if paths.is_empty() {
+                bail!("no file matches {}", pattern);
+            }
+            inputs.extend(paths.into_iter().map(Input::File));
+        } else {
+            inputs.push(Input::File(arg.clone()));
+        }
+    }
+
+    Ok(inputs)
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+type Puzzles<S = Sudoku> = Box<dyn Iterator<Item = Result<Puzzle<S>>> + Send>;
+
+/// Parses the text of a grid written in a file format.
+type GridParser<S> =
+    Arc<dyn Fn(&str, FileFormat) -> std::result::Result<S, ParseError> + Send + Sync>;
+
+/// What the grids of the inputs look like, and how to parse one.
+struct GridKind<S> {
+    /// Number of rows of the grids.
+    size: usize,
+    /// Lines of a grid in the multi-line formats: its rows, followed by the
+    /// rows of its regions for jigsaws.
+    lines: usize,
+    /// Whether the grids can be read from every format, and not only from
+    /// one-liner and multi-line files.
+    all_formats: bool,
+    parse: GridParser<S>,
+}
+
+// not derived, which would require `S: Clone`
+impl<S> Clone for GridKind<S> {
+    fn clone(&self) -> GridKind<S> {
+        GridKind {
+            parse: self.parse.clone(),
+            ..*self
+        }
+    }
+}
+
+impl GridKind<Sudoku> {
+    fn sudoku() -> GridKind<Sudoku> {
+        GridKind {
+            size: 9,
+            lines: 9,
+            all_formats: true,
+            parse: Arc::new(parse_sudoku),
+        }
+    }
+}
+
+/// Reads the sudokus of every input as they are needed, applying the
+/// `--on-error` policy to the malformed ones.
+fn read_puzzles(opt: &InputOpt) -> Result<Puzzles> {
+    read_grids(opt, GridKind::sudoku())
+}
+
+/// [`read_puzzles`] for boards of `geometry`, written with `symbols` or the
+/// ones detected in each grid.
+fn read_boards(
+    opt: &InputOpt,
+    geometry: Geometry,
+    symbols: Option<Symbols>,
+) -> Result<Puzzles<Board>> {
+    let size = geometry.size();
+    let geometry = Arc::new(geometry);
+    let parse = move |text: &str, _| {
+        let symbols = symbols.unwrap_or_else(|| Symbols::detect(text, size));
+        Board::parse_in(text, geometry.clone(), symbols)
+    };
+    read_grids(
+        opt,
+        GridKind {
+            size,
+            lines: size,
+            all_formats: false,
+            parse: Arc::new(parse),
+        },
+    )
+}
+
+/// [`read_puzzles`] for jigsaws of `size` rows, each grid being followed by
+/// the layout of its regions, to which `regions` are added.
+fn read_jigsaws(
+    opt: &InputOpt,
+    size: usize,
+    regions: Vec<Vec<usize>>,
+    symbols: Option<Symbols>,
+) -> Result<Puzzles<Board>> {
+    let parse = move |text: &str, _| {
+        let (cells, layout) = split_layout(text, size * size);
+        let geometry = Geometry::jigsaw(layout).map_err(|err| {
+            let (line, column) = text_position(text, cells.len());
+            ParseError {
+                line,
+                column,
+                kind: ParseErrorKind::InvalidRegions(err),
+            }
+        })?;
+        let symbols = symbols.unwrap_or_else(|| Symbols::detect(cells, size));
+        Board::parse_in(cells, Arc::new(geometry.extended(regions.clone())), symbols)
+    };
+    read_grids(
+        opt,
+        GridKind {
+            size,
+            lines: 2 * size,
+            all_formats: false,
+            parse: Arc::new(parse),
+        },
+    )
+}
+
+/// Reads the cages of the killer sudokus of every input, as the text each
+/// puzzle gives them in: a block of lines ended by a blank line, or a JSON
+/// object for the whole input. `#` comments give the metadata of a puzzle.
+fn read_cage_texts(opt: &InputOpt) -> Result<Puzzles<String>> {
+    let inputs = expand_inputs(&opt.files)?;
+    Ok(Box::new(inputs.into_iter().flat_map(|input| {
+        let name: Arc<str> = input.name().into();
+        let mut text = String::new();
+        let read = match input {
+            Input::Stdin => io::stdin().read_to_string(&mut text),
+            Input::File(ref path) => {
+                File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+            }
+        };
+        if let Err(err) = read {
+            return vec![Err(err).chain_err(|| format!("cannot read {}", name))];
+        }
+
+        split_cage_texts(&text)
+            .into_iter()
+            .map(|(line, text)| {
+                let metadata = text
+                    .lines()
+                    .filter_map(|line| line.trim_start().strip_prefix('#'))
+                    .flat_map(comment_metadata)
+                    .collect();
+                Ok(Puzzle {
+                    source: Source {
+                        name: name.clone(),
+                        line,
+                    },
+                    grid: text,
+                    metadata,
                 })
-                .filter(|content| !&content.is_empty() && !content.chars().all(char::is_whitespace))
-                .collect::<Vec<_>>();
+            })
+            .collect()
+    })))
+}
 
-            for nine_not_empty_lines in not_empty_lines.chunks(9) {
-                let mut grid = String::from("");
-                for lines in nine_not_empty_lines {
-                    grid.push_str(lines);
+/// [`read_puzzles`] for killer sudokus of `geometry`, their grids starting
+/// empty.
+fn read_killers(
+    opt: &InputOpt,
+    geometry: Geometry,
+    symbols: Option<Symbols>,
+) -> Result<Puzzles<Board>> {
+    let size = geometry.size();
+    let symbols = symbols.unwrap_or(Symbols::Digits);
+    let on_error = opt.on_error;
+    Ok(Box::new(read_cage_texts(opt)?.filter_map(move |puzzle| {
+        let puzzle = match puzzle {
+            Ok(puzzle) => puzzle,
+            Err(err) => return Some(Err(err)),
+        };
+        let cages = parse_cages(&puzzle.grid, size);
+        match cages.and_then(|cages| geometry.clone().with_cages(cages)) {
+            Ok(geometry) => Some(Ok(Puzzle {
+                grid: Board::new(Arc::new(geometry), symbols),
+                source: puzzle.source,
+                metadata: puzzle.metadata,
+            })),
+            Err(err) => match on_error {
+                OnError::Skip => {
+                    eprintln!("Skipping grid, {}: {}", puzzle.source, err);
+                    None
+                }
+                OnError::Abort => Some(Err(format!("{}: {}", puzzle.source, err).into())),
+            },
+        }
+    })))
+}
+
+/// Reads the grids of every input as described by `kind`.
+fn read_grids<S: Send + 'static>(opt: &InputOpt, kind: GridKind<S>) -> Result<Puzzles<S>> {
+    let input_format = opt.input_format;
+    let on_error = opt.on_error;
+
+    let inputs = expand_inputs(&opt.files)?;
+    Ok(Box::new(inputs.into_iter().flat_map(move |input| {
+        read_input_puzzles(input, input_format, on_error, kind.clone())
+    })))
+}
+
+fn read_input_puzzles<S: Send + 'static>(
+    input: Input,
+    input_format: Option<FileFormat>,
+    on_error: OnError,
+    kind: GridKind<S>,
+) -> Puzzles<S> {
+    let name: Arc<str> = input.name().into();
+    let read_error = |err| {
+        let err = Err(err).chain_err(|| format!("cannot read {}", name));
+        Box::new(std::iter::once(err))
+    };
+
+    let (mut reader, extension_format): (Box<dyn BufRead + Send>, _) = match input {
+        Input::Stdin => (Box::new(BufReader::new(io::stdin())), None),
+        Input::File(ref path) => match File::open(path) {
+            Ok(file) => (
+                Box::new(BufReader::new(file)),
+                FileFormat::from_extension(path),
+            ),
+            Err(err) => return read_error(err),
+        },
+    };
+
+    let input_format = match input_format.or(extension_format) {
+        Some(input_format) => input_format,
+        None => {
+            // the first lines tell the format, and are read again for the grids
+            let mut start = String::new();
+            for _ in 0..DETECTION_LINES {
+                match reader.read_line(&mut start) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(err) => return read_error(err),
                 }
-                grid_strings.push(grid);
             }
+            let input_format = FileFormat::detect(&start, kind.size, kind.all_formats);
+            reader = Box::new(io::Cursor::new(start.into_bytes()).chain(reader));
+            input_format
         }
+    };
+
+    if !kind.all_formats && !input_format.any_size() {
+        let err = format!(
+            "{}: only one-liner and multi-line files hold grids of other sizes or with regions",
+            name
+        );
+        return Box::new(std::iter::once(Err(err.into())));
+    }
 
-        InputFormat::OneLiner => {
-            for line in buffered.lines() {
-                let line_content = line?;
-                if !line_content.is_empty() {
-                    grid_strings.push(line_content);
+    let parse = kind.parse;
+    let grid_strings = GridStrings::new(reader, input_format, kind.lines);
+    Box::new(grid_strings.filter_map(move |grid_text| {
+        let grid_text = match grid_text {
+            Ok(grid_text) => grid_text,
+            Err(err) => return Some(Err(err).chain_err(|| format!("cannot read {}", name))),
+        };
+
+        match parse(&grid_text.text, input_format) {
+            Ok(grid) => Some(Ok(Puzzle {
+                source: Source {
+                    name: name.clone(),
+                    line: grid_text.line,
+                },
+                grid,
+                metadata: grid_text.metadata,
+            })),
+            Err(err) => {
+                let err = ParseError {
+                    line: grid_text.line + err.line - 1,
+                    ..err
+                };
+                match on_error {
+                    OnError::Skip => {
+                        eprintln!("Skipping grid, {}: {}", name, err);
+                        None
+                    }
+                    OnError::Abort => Some(Err(format!("{}: {}", name, err).into())),
                 }
             }
         }
+    }))
+}
+
+/// Parses the grids of every input, applying the `--on-error` policy to the
+/// malformed ones.
+fn load_puzzles(opt: &InputOpt) -> Result<Vec<Puzzle>> {
+    read_puzzles(opt)?.collect()
+}
+
+/// Largest number of puzzles read and solved at once in parallel, and of
+/// results held back to be printed in input order.
+const PUZZLES_IN_FLIGHT: usize = 1024;
+
+/// Hands results over in input order while they are produced in any order,
+/// holding only the ones finished before a result preceding them.
+struct ReorderBuffer<T> {
+    next: usize,
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> ReorderBuffer<T> {
+    fn new() -> ReorderBuffer<T> {
+        ReorderBuffer {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
     }
 
-    grid_strings
-        .par_iter()
-        .for_each(|grid_string_local| treat_grid(grid_string_local, opt.verbose));
+    /// Adds the result of rank `index`, then passes every result now in
+    /// order to `emit`.
+    fn push<F>(&mut self, index: usize, result: T, mut emit: F) -> io::Result<()>
+    where
+        F: FnMut(T) -> io::Result<()>,
+    {
+        self.pending.insert(index, result);
+        while let Some(result) = self.pending.remove(&self.next) {
+            emit(result)?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
 
-    Ok(())
+/// What is printed for a grid.
+enum Report {
+    Text(Vec<u8>),
+    Record(Record),
+}
+
+fn report<S: Solvable>(opt: &Opt, solver: &Solver, puzzle: &Puzzle<S>) -> Report {
+    let limit = if opt.unique { 2 } else { opt.limit };
+    let counting = opt.count || opt.unique;
+
+    if opt.output != OutputFormat::Text {
+        return Report::Record(if counting {
+            Record::counted(solver, puzzle, limit)
+        } else {
+            Record::solved(solver, puzzle)
+        });
+    }
+
+    let mut text = vec![];
+    // --explain conflicts with the options of other grid sizes
+    if let Some(sudoku) = puzzle.grid.sudoku().filter(|_| opt.explain) {
+        explain_grid(&puzzle.source, sudoku, &puzzle.comment(), &mut text);
+    } else if opt.pencil_marks && !opt.verbose {
+        pencil_mark_grids(solver, puzzle, counting.then_some(limit), &mut text);
+    } else if counting {
+        count_grid(
+            solver,
+            puzzle,
+            limit,
+            opt.verbose,
+            opt.pencil_marks,
+            opt.stats,
+            &mut text,
+        );
+    } else {
+        treat_grid(
+            solver,
+            puzzle,
+            opt.verbose,
+            opt.pencil_marks,
+            opt.stats,
+            &mut text,
+        );
+    }
+    Report::Text(text)
+}
+
+/// Where reports are printed, in input order unless `--unordered`.
+struct Printer {
+    records: Option<RecordWriter<io::Stdout>>,
+    reorder: Option<ReorderBuffer<Report>>,
+}
+
+impl Printer {
+    fn print(&mut self, index: usize, report: Report) -> io::Result<()> {
+        let records = &mut self.records;
+        let mut emit = |report| match report {
+            Report::Text(text) => io::stdout().write_all(&text),
+            Report::Record(record) => records.as_mut().unwrap().write(&record),
+        };
+        match self.reorder {
+            Some(ref mut reorder) => reorder.push(index, report, emit),
+            None => emit(report),
+        }
+    }
+}
+
+fn solve_file(opt: &Opt, pool: &Arc<ThreadPool>) -> Result<()> {
+    let board = opt.killer || opt.jigsaw || opt.board_shape()?.is_some();
+    if board && opt.engine == Engine::Dlx {
+        bail!("--engine dlx only solves classic sudokus, not other sizes, jigsaws or variants");
+    }
+    if opt.killer {
+        let shape = opt.board_shape()?.unwrap_or(BoxShape::CLASSIC);
+        let regions = opt.extra_regions(shape.size(), Some(shape));
+        let geometry = Geometry::with_regions(shape, regions);
+        return solve_puzzles(opt, pool, read_killers(&opt.input, geometry, opt.symbols)?);
+    }
+    if opt.jigsaw {
+        let size = opt.size.unwrap_or(9);
+        let regions = opt.extra_regions(size, None);
+        return solve_puzzles(
+            opt,
+            pool,
+            read_jigsaws(&opt.input, size, regions, opt.symbols)?,
+        );
+    }
+    match opt.board_shape()? {
+        Some(shape) => {
+            let regions = opt.extra_regions(shape.size(), Some(shape));
+            let geometry = Geometry::with_regions(shape, regions);
+            solve_puzzles(opt, pool, read_boards(&opt.input, geometry, opt.symbols)?)
+        }
+        None => solve_puzzles(opt, pool, read_puzzles(&opt.input)?),
+    }
+}
+
+fn solve_puzzles<S: Solvable>(
+    opt: &Opt,
+    pool: &Arc<ThreadPool>,
+    puzzles: Puzzles<S>,
+) -> Result<()> {
+    let solver = Solver::with_config(SolverConfig {
+        engine: opt.engine,
+        parallel_branches: opt.parallel.branches(),
+        deterministic: opt.deterministic,
+        timeout: opt.timeout_ms.map(Duration::from_millis),
+        cancellation: None,
+        thread_pool: Some(pool.clone()),
+    });
+
+    let records = match opt.output {
+        OutputFormat::Text => None,
+        format => Some(RecordWriter::new(format, opt.stats, io::stdout())?),
+    };
+    let printer = Mutex::new(Printer {
+        records,
+        reorder: if opt.unordered {
+            None
+        } else {
+            Some(ReorderBuffer::new())
+        },
+    });
+
+    let solve_puzzle = |(index, puzzle): (usize, Puzzle<S>)| -> Result<()> {
+        let report = report(opt, &solver, &puzzle);
+        printer.lock().unwrap().print(index, report)?;
+        Ok(())
+    };
+    // the puzzles stop at the first one that could not be read, the ones
+    // before it being all solved and printed
+    let mut read_error = None;
+    let mut puzzles = puzzles
+        .enumerate()
+        .map_while(|(index, puzzle)| match puzzle {
+            Ok(puzzle) => Some((index, puzzle)),
+            Err(err) => {
+                read_error = Some(err);
+                None
+            }
+        });
+    let solved = if !opt.parallel.puzzles() {
+        puzzles.try_for_each(solve_puzzle)
+    } else if opt.unordered {
+        // the threads take the next puzzle as soon as they are free
+        puzzles.par_bridge().try_for_each(solve_puzzle)
+    } else {
+        // a slow puzzle holds back at most the rest of its chunk, so that
+        // the results waiting for it stay few whatever the size of the input
+        loop {
+            let chunk = puzzles.by_ref().take(PUZZLES_IN_FLIGHT).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                break Ok(());
+            }
+            if let Err(err) = chunk.into_par_iter().try_for_each(solve_puzzle) {
+                break Err(err);
+            }
+        }
+    };
+    let solved = solved.and(read_error.map_or(Ok(()), Err));
+
+    // the records are closed even when stopping on an error, so that a JSON
+    // array stays valid
+    if let Some(records) = printer.into_inner().unwrap().records {
+        records.finish()?;
+    }
+    solved
 }
 
 quick_main!(run);
@@ -0,0 +1,248 @@
+//! Difficulty rating, on a scale compatible with Sudoku Explainer (SE).
+
+use std::fmt;
+
+use crate::grid::Grid;
+use crate::logic::{solve_logically, Technique};
+use crate::solver::{fill_hidden_singles, least_possibilities_cell, set_cell_value_at};
+use crate::sudoku::Sudoku;
+
+/// Rating of the puzzles that can be solved by the known techniques only
+/// start under this value, the ones needing guesses start at it.
+const GUESSING_RATING: f32 = 6.0;
+
+/// Highest rating given, the one of the hardest known puzzles.
+const MAX_RATING: f32 = 11.9;
+
+impl Technique {
+    /// Rating of the technique on the Sudoku Explainer scale.
+    pub fn se_rating(self) -> f32 {
+        match self {
+            Technique::HiddenSingle => 1.5,
+            Technique::NakedSingle => 2.3,
+            Technique::PointingPair => 2.6,
+            Technique::BoxLineReduction => 2.8,
+            Technique::NakedPair => 3.0,
+            Technique::XWing => 3.2,
+            Technique::HiddenPair => 3.4,
+            Technique::NakedTriple => 3.6,
+            Technique::Swordfish => 3.8,
+            Technique::HiddenTriple => 4.0,
+            Technique::XYWing => 4.2,
+            Technique::SimpleColoring => 4.5,
+            Technique::NakedQuad => 5.0,
+            Technique::HiddenQuad => 5.4,
+        }
+    }
+}
+
+/// Label of a rating, for people who do not know the SE scale.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Difficulty {
+    /// Singles only.
+    Easy,
+    /// Up to pointing pairs, box/line reductions, X-Wings and pairs.
+    Medium,
+    /// Up to triples, quads, Swordfish, XY-Wings and coloring.
+    Hard,
+    /// Needs guessing.
+    Diabolical,
+}
+
+impl Difficulty {
+    fn from_rating(rating: f32) -> Difficulty {
+        if rating <= Technique::NakedSingle.se_rating() {
+            Difficulty::Easy
+        } else if rating <= Technique::HiddenPair.se_rating() {
+            Difficulty::Medium
+        } else if rating < GUESSING_RATING {
+            Difficulty::Hard
+        } else {
+            Difficulty::Diabolical
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Diabolical => "diabolical",
+        })
+    }
+}
+
+/// Result of [`rate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rating {
+    /// Rating on the SE scale, from 1.5 to 11.9.
+    pub score: f32,
+    pub difficulty: Difficulty,
+    /// Hardest technique used before the techniques ran out.
+    pub hardest_technique: Option<Technique>,
+    /// Guesses made once no technique applied anymore.
+    pub guesses: usize,
+    /// Guesses that turned out wrong.
+    pub backtracks: usize,
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} {}", self.score, self.difficulty)
+    }
+}
+
+/// Rates `sudoku` by the hardest technique it needs.
+///
+/// Puzzles the techniques of [`solve_logically`] cannot finish are rated
+/// from 6.0 upwards by the number of backtracks a search in a fixed order
+/// needs from where the techniques stopped, so the rating is the same on
+/// every run and every machine. Returns `None` when the givens conflict or
+/// the puzzle has no solution.
+pub fn rate(sudoku: &Sudoku) -> Option<Rating> {
+    if !sudoku.conflicts().is_empty() {
+        return None;
+    }
+
+    let logical = solve_logically(sudoku);
+    let hardest_technique = logical.hardest_technique();
+
+    let (score, guesses, backtracks) = if logical.is_solved() {
+        (hardest_technique.map_or(1.0, Technique::se_rating), 0, 0)
+    } else {
+        let mut search = Search::default();
+        if !search.solve(*logical.sudoku.grid()) {
+            return None;
+        }
+        let score = GUESSING_RATING + 0.5 * (1.0 + search.backtracks as f32).log2();
+        (score.min(MAX_RATING), search.guesses, search.backtracks)
+    };
+
+    // keep a single decimal so that the score prints and compares the same
+    let score = (score * 10.0).round() / 10.0;
+
+    Some(Rating {
+        score,
+        difficulty: Difficulty::from_rating(score),
+        hardest_technique,
+        guesses,
+        backtracks,
+    })
+}
+
+#[derive(Default)]
+struct Search {
+    guesses: usize,
+    backtracks: usize,
+}
+
+impl Search {
+    /// Depth first search trying the values in increasing order.
+    fn solve(&mut self, mut grid: Grid) -> bool {
+        if !fill_hidden_singles(&mut grid) {
+            return false;
+        }
+
+        match least_possibilities_cell(&grid) {
+            Some((index, poss)) => {
+                for cell_value in poss {
+                    self.guesses += 1;
+                    let mut new_g = grid;
+                    if set_cell_value_at(&mut new_g, index, cell_value) && self.solve(new_g) {
+                        return true;
+                    }
+                    self.backtracks += 1;
+                }
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_text(text: &str) -> Option<Rating> {
+        rate(&Sudoku::parse(text).unwrap())
+    }
+
+    /// Score, label and hardest technique of `text`.
+    fn summary(text: &str) -> (f32, Difficulty, Option<Technique>) {
+        let rating = rate_text(text).unwrap();
+        (rating.score, rating.difficulty, rating.hardest_technique)
+    }
+
+    #[test]
+    fn logical_puzzles() {
+        assert_eq!(
+            summary(
+                ".6..7.2...25....8.4..2.1.63..75..4......4......1..93..69.1.2..5.1....63...3.6..2."
+            ),
+            (1.5, Difficulty::Easy, Some(Technique::HiddenSingle))
+        );
+        assert_eq!(
+            summary(
+                "..............3.85..1.2.......5.7.....4...1...9.......5......73..2.1........4...9"
+            ),
+            (2.3, Difficulty::Easy, Some(Technique::NakedSingle))
+        );
+        assert_eq!(
+            summary(
+                "1.....569492.561.8.561.924...964.8.1.64.1....218.356.4.4.5...169.5.614.2621.....5"
+            ),
+            (3.2, Difficulty::Medium, Some(Technique::XWing))
+        );
+        assert_eq!(
+            summary(
+                "..9.....3.....9...7.....5.6..65..4.....3......28......3..75.6..6...........12.3.8"
+            ),
+            (4.2, Difficulty::Hard, Some(Technique::XYWing))
+        );
+    }
+
+    #[test]
+    fn guessed_puzzles() {
+        let text =
+            "48.3............71.2.......7.5....6....2..8.............1.76...3.....4......5....";
+        let rating = rate_text(text).unwrap();
+        assert_eq!(rating.to_string(), "6.5 diabolical");
+        assert_eq!(rating.hardest_technique, Some(Technique::XYWing));
+        assert_eq!((rating.guesses, rating.backtracks), (4, 1));
+        // the empty grid needs guesses from the start, never a wrong one
+        let rating = rate_text(&".".repeat(81)).unwrap();
+        assert_eq!((rating.score, rating.hardest_technique), (6.0, None));
+        assert_eq!(rating.backtracks, 0);
+    }
+
+    #[test]
+    fn invalid_puzzles() {
+        // two 1 in the first row
+        assert_eq!(rate_text(&format!("1.1{}", ".".repeat(78))), None);
+        // r1c9 has no value left
+        assert_eq!(
+            rate_text(&format!("12345678.........9{}", ".".repeat(63))),
+            None
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let labels = [
+            (1.5, Difficulty::Easy),
+            (2.3, Difficulty::Easy),
+            (2.6, Difficulty::Medium),
+            (3.4, Difficulty::Medium),
+            (3.6, Difficulty::Hard),
+            (5.4, Difficulty::Hard),
+            (6.0, Difficulty::Diabolical),
+            (11.9, Difficulty::Diabolical),
+        ];
+        for (score, difficulty) in labels {
+            assert_eq!(Difficulty::from_rating(score), difficulty, "{}", score);
+        }
+    }
+}
@@ -0,0 +1,988 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use crate::board::Board;
+use crate::dlx::{search_grid_dlx, search_solution_count_dlx};
+use crate::geometry::{HOUSES, PEERS};
+use crate::grid::{Candidates, Conflict, Grid};
+use crate::sudoku::Sudoku;
+
+/// How a solve attempt ended, for a [`Sudoku`] or a [`Board`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<S = Sudoku> {
+    /// A solution was found.
+    Solved(Box<S>),
+    /// The givens admit no solution.
+    Unsolvable,
+    /// Some givens contradict each other, the grid was not searched.
+    Invalid(Vec<Conflict>),
+    /// The search was stopped before finding a solution.
+    Interrupted(Interruption),
+}
+
+/// Why a search was stopped before it was over.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interruption {
+    /// [`SolverConfig::timeout`] elapsed.
+    Timeout,
+    /// [`SolverConfig::cancellation`] was cancelled.
+    Cancelled,
+}
+
+/// Stops the searches of the solvers it was given to, from any thread.
+/// Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Makes the running and future searches stop as soon as possible.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Result of [`Solver::solve`] and [`Solver::solve_board`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolveResult<S = Sudoku> {
+    pub outcome: Outcome<S>,
+    /// Wall-clock time spent solving.
+    pub elapsed: Duration,
+    /// With the parallel search, this includes the branches explored while
+    /// another thread found the solution.
+    pub stats: SolveStats,
+}
+
+/// Counters of a search, to compare algorithms without relying on timings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SolveStats {
+    /// Nodes of the search tree visited.
+    pub nodes: usize,
+    /// Values tried in cells left with several possibilities once
+    /// propagation stalled.
+    pub guesses: usize,
+    /// Branches abandoned because a cell or a value of a house was left
+    /// without any possibility.
+    pub dead_ends: usize,
+    /// Largest number of nested guesses.
+    pub max_depth: usize,
+    /// Cells filled by propagation rather than guessed.
+    pub propagated: usize,
+}
+
+impl fmt::Display for SolveStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} guesses, {} dead ends, max depth {}, {} cells propagated",
+            self.nodes, self.guesses, self.dead_ends, self.max_depth, self.propagated
+        )
+    }
+}
+
+impl<S> SolveResult<S> {
+    /// The solution, if one was found.
+    pub fn solution(&self) -> Option<&S> {
+        match self.outcome {
+            Outcome::Solved(ref sudoku) => Some(sudoku),
+            Outcome::Unsolvable | Outcome::Invalid(_) | Outcome::Interrupted(_) => None,
+        }
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.solution().is_some()
+    }
+}
+
+/// Result of [`count_solutions`] and [`Solver::count_board_solutions`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolutionCount<S = Sudoku> {
+    /// Number of solutions found, never more than `limit`.
+    pub count: usize,
+    /// The cap the search was given.
+    pub limit: usize,
+    /// The first two solutions found. When there are two, the second one is
+    /// a witness that the puzzle is not unique.
+    pub solutions: Vec<S>,
+    pub stats: SolveStats,
+    /// Set when the search was stopped before it was over, `count` being
+    /// then only a lower bound.
+    pub interrupted: Option<Interruption>,
+}
+
+impl<S> SolutionCount<S> {
+    /// Returns true when the puzzle has exactly one solution. An interrupted
+    /// search may have stopped before the second one.
+    pub fn is_unique(&self) -> bool {
+        self.count == 1 && self.limit > 1 && self.interrupted.is_none()
+    }
+
+    /// Returns true when the search stopped because it reached the limit, in
+    /// which case there may be more solutions.
+    pub fn is_capped(&self) -> bool {
+        self.count >= self.limit
+    }
+
+    /// A second solution, proving that the puzzle is not unique.
+    pub fn witness(&self) -> Option<&S> {
+        self.solutions.get(1)
+    }
+}
+
+impl<S> fmt::Display for SolutionCount<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.interrupted.is_some() || (self.is_capped() && self.count > 0) {
+            write!(f, "{}+", self.count)
+        } else {
+            write!(f, "{}", self.count)
+        }
+    }
+}
+
+/// Algorithm used by a [`Solver`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Constraint propagation followed by a parallel search on the cell with
+    /// the fewest possibilities.
+    #[default]
+    Propagation,
+    /// Dancing Links exact cover search.
+    Dlx,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "propagation" => Ok(Engine::Propagation),
+            "dlx" => Ok(Engine::Dlx),
+            _ => Err(format!(
+                "unknown engine '{}', expected propagation or dlx",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Engine::Propagation => "propagation",
+            Engine::Dlx => "dlx",
+        })
+    }
+}
+
+/// Parameters of a [`Solver`].
+#[derive(Clone, Debug)]
+pub struct SolverConfig {
+    pub engine: Engine,
+    /// Explore the values of a guessed cell in parallel. Only the
+    /// propagation engine searches in parallel.
+    pub parallel_branches: bool,
+    /// Search sequentially, guessing the cells in order and their values in
+    /// increasing order, so that the solution found is always the
+    /// lexicographically smallest one. Overrides `parallel_branches`.
+    pub deterministic: bool,
+    /// Pool the parallel search runs in, rayon's global pool when `None`.
+    pub thread_pool: Option<Arc<ThreadPool>>,
+    /// Longest time spent on a single puzzle, its search is stopped after.
+    pub timeout: Option<Duration>,
+    /// Stops the searches once cancelled.
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl Default for SolverConfig {
+    fn default() -> SolverConfig {
+        SolverConfig {
+            engine: Engine::Propagation,
+            parallel_branches: true,
+            deterministic: false,
+            thread_pool: None,
+            timeout: None,
+            cancellation: None,
+        }
+    }
+}
+
+/// Entry point of the library: solves sudokus with one of the [`Engine`]s,
+/// constraint propagation by default.
+#[derive(Clone, Debug, Default)]
+pub struct Solver {
+    config: SolverConfig,
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        Solver::default()
+    }
+
+    pub fn with_engine(engine: Engine) -> Solver {
+        Solver::with_config(SolverConfig {
+            engine,
+            ..SolverConfig::default()
+        })
+    }
+
+    pub fn with_config(config: SolverConfig) -> Solver {
+        Solver { config }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.config.engine
+    }
+
+    pub fn config(&self) -> &SolverConfig {
+        &self.config
+    }
+
+    /// A search starting now, following the configuration.
+    fn search_state(&self) -> SearchState {
+        let branching = if self.config.deterministic {
+            Branching::Ordered
+        } else if self.config.parallel_branches {
+            Branching::Parallel
+        } else {
+            Branching::Sequential
+        };
+        SearchState {
+            deadline: self.config.timeout.map(|timeout| Instant::now() + timeout),
+            cancellation: self.config.cancellation.clone(),
+            ..SearchState::new(branching)
+        }
+    }
+
+    /// Runs `op` in the configured thread pool.
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> R {
+        match self.config.thread_pool {
+            Some(ref pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Solves `sudoku`, timing the attempt. Givens contradicting each other
+    /// are reported without searching.
+    pub fn solve(&self, sudoku: &Sudoku) -> SolveResult {
+        let now = Instant::now();
+        let state = self.search_state();
+
+        let conflicts = sudoku.conflicts();
+        if !conflicts.is_empty() {
+            return SolveResult {
+                outcome: Outcome::Invalid(conflicts),
+                elapsed: now.elapsed(),
+                stats: SolveStats::default(),
+            };
+        }
+
+        let grid = *sudoku.grid();
+        let solution = match self.config.engine {
+            Engine::Propagation => self.install(|| search_grid(grid, &state)),
+            Engine::Dlx => search_grid_dlx(grid, &state),
+        };
+        solve_result(solution.map(Sudoku::from_grid), now, &state)
+    }
+
+    /// Solves a `board` of any size. Boards are always searched with
+    /// propagation, whatever the engine.
+    pub fn solve_board(&self, board: &Board) -> SolveResult<Board> {
+        let now = Instant::now();
+        let state = self.search_state();
+
+        let conflicts = board.conflicts();
+        if !conflicts.is_empty() {
+            return SolveResult {
+                outcome: Outcome::Invalid(conflicts),
+                elapsed: now.elapsed(),
+                stats: SolveStats::default(),
+            };
+        }
+
+        let solution = self.install(|| search(board.clone(), &state));
+        solve_result(solution, now, &state)
+    }
+
+    /// Counts the solutions of `sudoku`, stopping once `limit` are found.
+    /// A limit of 2 is enough to tell whether a puzzle is proper.
+    pub fn count_solutions(&self, sudoku: &Sudoku, limit: usize) -> SolutionCount {
+        if !sudoku.conflicts().is_empty() {
+            return SolutionCount {
+                count: 0,
+                limit,
+                solutions: vec![],
+                stats: SolveStats::default(),
+                interrupted: None,
+            };
+        }
+        let grid = *sudoku.grid();
+        let state = self.search_state();
+        match self.config.engine {
+            Engine::Propagation => self.install(|| search_solution_count(grid, limit, &state)),
+            Engine::Dlx => search_solution_count_dlx(grid, limit, &state),
+        }
+    }
+
+    /// Counts the solutions of a `board` of any size, stopping once `limit`
+    /// are found.
+    pub fn count_board_solutions(&self, board: &Board, limit: usize) -> SolutionCount<Board> {
+        if !board.conflicts().is_empty() {
+            return SolutionCount {
+                count: 0,
+                limit,
+                solutions: vec![],
+                stats: SolveStats::default(),
+                interrupted: None,
+            };
+        }
+        let state = self.search_state();
+        self.install(|| search_count(board.clone(), limit, &state))
+    }
+}
+
+/// The result of a search started at `start`.
+fn solve_result<S>(solution: Option<S>, start: Instant, state: &SearchState) -> SolveResult<S> {
+    let elapsed = start.elapsed();
+    let outcome = match (solution, state.interruption()) {
+        (Some(solution), _) => Outcome::Solved(Box::new(solution)),
+        (None, Some(interruption)) => Outcome::Interrupted(interruption),
+        (None, None) => Outcome::Unsolvable,
+    };
+
+    SolveResult {
+        outcome,
+        elapsed,
+        stats: state.stats(),
+    }
+}
+
+/// The 20 cells sharing a house with the cell at `index`.
+pub(crate) fn get_adjacent_cells(index: usize) -> [usize; 20] {
+    PEERS[index]
+}
+
+/// Restricts the possibilities of every empty cell to the values its
+/// houses allow, then places the cells left with a single possibility.
+pub(crate) fn build_possible_values_grid(grid: &mut Grid) -> bool {
+    for index in 0..81 {
+        if !grid.is_value(index) {
+            let poss = grid.candidates(index) & grid.allowed(index);
+            grid.set_candidates(index, poss);
+            if poss.is_empty() {
+                return false;
+            }
+            if let Some(value) = poss.single() {
+                if !set_cell_value_at(grid, index, value) {
+                    return false;
+                }
+            }
+        }
+    }
+    fill_hidden_singles(grid)
+}
+
+fn fill_one_possibility_cells(grid: &mut Grid, values: [usize; 20]) -> bool {
+    for &val in &values {
+        if !grid.is_value(val) {
+            let poss = grid.candidates(val);
+            if poss.is_empty() {
+                return false;
+            }
+            if let Some(value) = poss.single() {
+                if !set_cell_value_at(grid, val, value) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Places a value then propagates: the cells sharing a house with it that
+/// are left with a single possibility are filled in turn. Returns false
+/// when a cell ends up without any possibility.
+pub(crate) fn set_cell_value_at(grid: &mut Grid, index: usize, cell_value: usize) -> bool {
+    grid.place(index, cell_value);
+
+    fill_one_possibility_cells(grid, get_adjacent_cells(index))
+}
+
+/// Places the values that are possible in a single cell of a house, until
+/// there are none left. Returns false when a value has no place left in a
+/// house.
+pub(crate) fn fill_hidden_singles(grid: &mut Grid) -> bool {
+    loop {
+        let mut progress = false;
+
+        for (house, cells) in HOUSES.iter().enumerate() {
+            let mut once = Candidates::NONE;
+            let mut twice = Candidates::NONE;
+            for &cell in cells {
+                let poss = grid.candidates(cell);
+                twice |= once & poss;
+                once |= poss;
+            }
+
+            let placed = grid.placed(house);
+            if once | placed != Candidates::ALL {
+                return false;
+            }
+
+            for value in once & !twice {
+                let cell = cells.iter().find(|&&c| grid.candidates(c).contains(value));
+                if let Some(&cell) = cell {
+                    if !set_cell_value_at(grid, cell, value) {
+                        return false;
+                    }
+                    progress = true;
+                }
+            }
+        }
+
+        if !progress {
+            return true;
+        }
+    }
+}
+
+/// The empty cell with the fewest possibilities, the best one to guess.
+pub(crate) fn least_possibilities_cell(grid: &Grid) -> Option<(usize, Candidates)> {
+    let mut best: Option<(usize, Candidates)> = None;
+    for index in (0..81).filter(|&i| !grid.is_value(i)) {
+        let poss = grid.candidates(index);
+        if best.is_none_or(|(_, b)| poss.len() < b.len()) {
+            best = Some((index, poss));
+            if poss.len() <= 2 {
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// How the search picks the cell to guess and goes through its values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Branching {
+    /// The cell with the fewest possibilities, its values in parallel.
+    Parallel,
+    /// The cell with the fewest possibilities, its values in increasing
+    /// order.
+    Sequential,
+    /// The first empty cell, its values in increasing order: the solutions
+    /// are found in lexicographic order.
+    Ordered,
+}
+
+impl Branching {
+    fn guessed_cell(self, grid: &Grid) -> Option<(usize, Candidates)> {
+        match self {
+            Branching::Parallel | Branching::Sequential => least_possibilities_cell(grid),
+            Branching::Ordered => (0..81)
+                .find(|&index| !grid.is_value(index))
+                .map(|index| (index, grid.candidates(index))),
+        }
+    }
+
+    /// Calls `f` with each value of `poss`, in parallel or in increasing
+    /// order.
+    pub(crate) fn for_each_value<I, F>(self, poss: I, f: F)
+    where
+        I: IntoIterator<Item = usize>,
+        F: Fn(usize) + Send + Sync,
+    {
+        if self == Branching::Parallel {
+            poss.into_iter()
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .for_each(f);
+        } else {
+            poss.into_iter().for_each(f);
+        }
+    }
+}
+
+/// [`SolveStats`] updated from several threads.
+#[derive(Default)]
+struct StatsCounters {
+    nodes: AtomicUsize,
+    guesses: AtomicUsize,
+    dead_ends: AtomicUsize,
+    max_depth: AtomicUsize,
+    propagated: AtomicUsize,
+}
+
+/// What the branches of a search share: how to branch, when to stop, and
+/// the statistics so far.
+pub(crate) struct SearchState {
+    pub(crate) branching: Branching,
+    stats: StatsCounters,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    interruption: OnceLock<Interruption>,
+}
+
+impl SearchState {
+    pub(crate) fn new(branching: Branching) -> SearchState {
+        SearchState {
+            branching,
+            stats: StatsCounters::default(),
+            deadline: None,
+            cancellation: None,
+            interruption: OnceLock::new(),
+        }
+    }
+
+    /// Counts a node of the search tree, under `depth` guesses.
+    pub(crate) fn visit(&self, depth: usize) {
+        self.stats.nodes.fetch_add(1, Ordering::Relaxed);
+        self.stats.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub(crate) fn guess(&self) {
+        self.stats.guesses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Runs `propagate`, counting the cells it fills and whether it hit a
+    /// dead end.
+    fn propagate<F: FnOnce(&mut Grid) -> bool>(&self, grid: &mut Grid, propagate: F) -> bool {
+        let before = grid.value_count();
+        let possible = propagate(grid);
+        self.count_propagation(grid.value_count() - before, possible);
+        possible
+    }
+
+    /// [`set_cell_value_at`], counted as a guess followed by propagation.
+    fn place_guess(&self, grid: &mut Grid, index: usize, value: usize) -> bool {
+        self.guess();
+        let before = grid.value_count();
+        let possible = set_cell_value_at(grid, index, value);
+        self.count_propagation(grid.value_count() - before - 1, possible);
+        possible
+    }
+
+    pub(crate) fn count_propagation(&self, filled: usize, possible: bool) {
+        self.stats.propagated.fetch_add(filled, Ordering::Relaxed);
+        if !possible {
+            self.stats.dead_ends.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Adds the statistics of a search made outside of this state.
+    pub(crate) fn add_stats(&self, stats: &SolveStats) {
+        let counters = &self.stats;
+        counters.nodes.fetch_add(stats.nodes, Ordering::Relaxed);
+        counters.guesses.fetch_add(stats.guesses, Ordering::Relaxed);
+        counters
+            .dead_ends
+            .fetch_add(stats.dead_ends, Ordering::Relaxed);
+        counters
+            .max_depth
+            .fetch_max(stats.max_depth, Ordering::Relaxed);
+        counters
+            .propagated
+            .fetch_add(stats.propagated, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> SolveStats {
+        let counters = &self.stats;
+        SolveStats {
+            nodes: counters.nodes.load(Ordering::Relaxed),
+            guesses: counters.guesses.load(Ordering::Relaxed),
+            dead_ends: counters.dead_ends.load(Ordering::Relaxed),
+            max_depth: counters.max_depth.load(Ordering::Relaxed),
+            propagated: counters.propagated.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true once the search has to stop, remembering why.
+    pub(crate) fn is_interrupted(&self) -> bool {
+        if self.interruption.get().is_some() {
+            return true;
+        }
+        let interruption = if self.cancellation.as_ref().is_some_and(|c| c.is_cancelled()) {
+            Interruption::Cancelled
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Interruption::Timeout
+        } else {
+            return false;
+        };
+        let _ = self.interruption.set(interruption);
+        true
+    }
+
+    pub(crate) fn interruption(&self) -> Option<Interruption> {
+        self.interruption.get().copied()
+    }
+}
+
+/// A grid the propagation search explores: the classic [`Grid`] or a
+/// [`Board`] of any geometry.
+pub(crate) trait Searchable: Clone + Send + Sync {
+    type Values: IntoIterator<Item = usize>;
+
+    /// Restricts the candidates to what the givens allow, once before the
+    /// search. Returns false when the givens admit no solution.
+    fn propagate_givens(&mut self, state: &SearchState) -> bool;
+
+    /// Fills what the values placed imply at a node of the search. Returns
+    /// false at a dead end.
+    fn propagate(&mut self, state: &SearchState) -> bool;
+
+    /// The empty cell to guess with its candidates, `None` once the grid is
+    /// full.
+    fn guessed_cell(&self, branching: Branching) -> Option<(usize, Self::Values)>;
+
+    /// Places a guessed value. Returns false at a dead end.
+    fn place_guess(&mut self, index: usize, value: usize, state: &SearchState) -> bool;
+}
+
+impl Searchable for Grid {
+    type Values = Candidates;
+
+    fn propagate_givens(&mut self, state: &SearchState) -> bool {
+        state.propagate(self, build_possible_values_grid)
+    }
+
+    fn propagate(&mut self, state: &SearchState) -> bool {
+        state.propagate(self, fill_hidden_singles)
+    }
+
+    fn guessed_cell(&self, branching: Branching) -> Option<(usize, Candidates)> {
+        branching.guessed_cell(self)
+    }
+
+    fn place_guess(&mut self, index: usize, value: usize, state: &SearchState) -> bool {
+        state.place_guess(self, index, value)
+    }
+}
+
+/// Solves a grid, returning the first solution found or `None` when the
+/// givens admit no solution.
+pub fn solve_grid(grid: Grid) -> Option<Grid> {
+    search_grid(grid, &SearchState::new(Branching::Parallel))
+}
+
+/// [`solve_grid`], following `state`.
+pub(crate) fn search_grid(grid: Grid, state: &SearchState) -> Option<Grid> {
+    search(grid, state)
+}
+
+/// Searches `grid` following `state`, returning the first solution found or
+/// `None` when it has none.
+pub(crate) fn search<G: Searchable>(mut grid: G, state: &SearchState) -> Option<G> {
+    if !grid.propagate_givens(state) {
+        return None;
+    }
+    search_recurse(grid, &RwLock::new(None), state, 0)
+}
+
+fn search_recurse<G: Searchable>(
+    mut grid: G,
+    found: &RwLock<Option<G>>,
+    state: &SearchState,
+    depth: usize,
+) -> Option<G> {
+    state.visit(depth);
+    if state.is_interrupted() || !grid.propagate(state) {
+        return None;
+    }
+
+    let branching = state.branching;
+    let Some((index, values)) = grid.guessed_cell(branching) else {
+        return Some(grid);
+    };
+    branching.for_each_value(values, |value| {
+        if found.read().unwrap().is_none() {
+            let mut guessed = grid.clone();
+            if guessed.place_guess(index, value, state) && found.read().unwrap().is_none() {
+                if let Some(solution) = search_recurse(guessed, found, state, depth + 1) {
+                    *found.write().unwrap() = Some(solution);
+                }
+            }
+        }
+    });
+    found.read().unwrap().clone()
+}
+
+/// Counts the solutions of a grid, exploring every branch until `limit`
+/// solutions are found.
+pub fn count_solutions(grid: Grid, limit: usize) -> SolutionCount {
+    search_solution_count(grid, limit, &SearchState::new(Branching::Parallel))
+}
+
+/// [`count_solutions`], following `state`.
+pub(crate) fn search_solution_count(
+    grid: Grid,
+    limit: usize,
+    state: &SearchState,
+) -> SolutionCount {
+    let count = search_count(grid, limit, state);
+    SolutionCount {
+        count: count.count,
+        limit,
+        solutions: count.solutions.into_iter().map(Sudoku::from_grid).collect(),
+        stats: count.stats,
+        interrupted: count.interrupted,
+    }
+}
+
+/// Counts the solutions of `grid` following `state`, until `limit` are
+/// found.
+pub(crate) fn search_count<G: Searchable>(
+    mut grid: G,
+    limit: usize,
+    state: &SearchState,
+) -> SolutionCount<G> {
+    let found = AtomicUsize::new(0);
+    let solutions = Mutex::new(vec![]);
+
+    if limit > 0 && grid.propagate_givens(state) {
+        count_recurse(grid, limit, &found, &solutions, state, 0);
+    }
+
+    SolutionCount {
+        count: found.into_inner().min(limit),
+        limit,
+        solutions: solutions.into_inner().unwrap(),
+        stats: state.stats(),
+        interrupted: state.interruption(),
+    }
+}
+
+fn count_recurse<G: Searchable>(
+    mut grid: G,
+    limit: usize,
+    found: &AtomicUsize,
+    solutions: &Mutex<Vec<G>>,
+    state: &SearchState,
+    depth: usize,
+) {
+    if found.load(Ordering::Relaxed) >= limit || state.is_interrupted() {
+        return;
+    }
+    state.visit(depth);
+    if !grid.propagate(state) {
+        return;
+    }
+
+    let branching = state.branching;
+    match grid.guessed_cell(branching) {
+        Some((index, values)) => {
+            branching.for_each_value(values, |value| {
+                let mut guessed = grid.clone();
+                if guessed.place_guess(index, value, state) {
+                    count_recurse(guessed, limit, found, solutions, state, depth + 1);
+                }
+            });
+        }
+        None => {
+            // every cell holds a value: this branch is a solution
+            let rank = found.fetch_add(1, Ordering::Relaxed);
+            if rank < 2 && rank < limit {
+                solutions.lock().unwrap().push(grid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIQUE: &str =
+        "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......";
+    // UNIQUE without its r5c7, 794 solutions
+    const SEVERAL: &str =
+        "4.....8.5.3..........7......2.....6.....8........1.......6.3.7.5..2.....1.4......";
+    // r1c9 has no value left, though no house repeats a given
+    const CONTRADICTORY: &str =
+        "12345678.........9...............................................................";
+    // two 1 in the first row
+    const CONFLICTING: &str =
+        "1.1..............................................................................";
+
+    fn solvers(config: SolverConfig) -> [Solver; 2] {
+        [Engine::Propagation, Engine::Dlx].map(|engine| {
+            Solver::with_config(SolverConfig {
+                engine,
+                ..config.clone()
+            })
+        })
+    }
+
+    /// The lexicographically smallest solution, by trying the values of the
+    /// first empty cell in increasing order, giving up on a grid only when
+    /// an empty cell has no value left.
+    fn smallest_solution(grid: Grid) -> Option<Grid> {
+        let empty = (0..81).filter(|&index| !grid.is_value(index));
+        if empty.clone().any(|index| grid.allowed(index).is_empty()) {
+            return None;
+        }
+        let Some(index) = empty.min() else {
+            return Some(grid);
+        };
+        grid.allowed(index).iter().find_map(|value| {
+            let mut next = grid;
+            next.place(index, value);
+            smallest_solution(next)
+        })
+    }
+
+    #[test]
+    fn deterministic_solutions_are_the_smallest() {
+        // the solution of UNIQUE with two thirds of its cells cleared
+        let solution = Solver::new().solve(&Sudoku::parse(UNIQUE).unwrap());
+        let mut open = *solution.solution().unwrap().grid();
+        for index in (0..81).filter(|index| index % 3 != 0) {
+            open.clear(index);
+        }
+        let open = Sudoku::from_grid(open).to_line();
+        let sparse = format!("{}5{}", ".".repeat(40), ".".repeat(40));
+        for text in [&open, &sparse] {
+            let sudoku = Sudoku::parse(text).unwrap();
+            assert!(count_solutions(*sudoku.grid(), 2).count > 1, "{}", text);
+            let smallest = smallest_solution(*sudoku.grid()).unwrap();
+            let smallest = Sudoku::from_grid(smallest).to_line();
+            for solver in solvers(SolverConfig {
+                deterministic: true,
+                ..SolverConfig::default()
+            }) {
+                let result = solver.solve(&sudoku);
+                assert_eq!(
+                    result.solution().unwrap().to_line(),
+                    smallest,
+                    "{} {}",
+                    solver.engine(),
+                    text
+                );
+                let count = solver.count_solutions(&sudoku, 2);
+                assert_eq!(count.solutions[0].to_line(), smallest);
+            }
+        }
+    }
+
+    #[test]
+    fn unique_count() {
+        let sudoku = Sudoku::parse(UNIQUE).unwrap();
+        let solution = Solver::new().solve(&sudoku).solution().unwrap().to_line();
+        for solver in solvers(SolverConfig::default()) {
+            let count = solver.count_solutions(&sudoku, 2);
+            assert_eq!((count.count, count.limit), (1, 2), "{}", solver.engine());
+            assert!(count.is_unique() && !count.is_capped());
+            assert_eq!(count.solutions.len(), 1);
+            assert_eq!(count.solutions[0].to_line(), solution);
+            assert_eq!(count.witness(), None);
+            assert_eq!(count.interrupted, None);
+            assert_eq!(count.to_string(), "1");
+        }
+    }
+
+    #[test]
+    fn capped_count() {
+        let sudoku = Sudoku::parse(SEVERAL).unwrap();
+        for solver in solvers(SolverConfig::default()) {
+            let count = solver.count_solutions(&sudoku, 10);
+            assert_eq!(count.count, 10, "{}", solver.engine());
+            assert!(count.is_capped() && !count.is_unique());
+            assert_eq!(count.to_string(), "10+");
+            // the first two solutions are kept, the second one as a witness
+            assert_eq!(count.solutions.len(), 2);
+            let witness = count.witness().unwrap();
+            assert_ne!(witness, &count.solutions[0]);
+            assert!(witness.conflicts().is_empty());
+
+            let count = solver.count_solutions(&sudoku, 1000);
+            assert_eq!(count.count, 794, "{}", solver.engine());
+            assert!(!count.is_capped());
+            assert_eq!(count.to_string(), "794");
+        }
+    }
+
+    #[test]
+    fn zero_counts() {
+        for text in [CONTRADICTORY, CONFLICTING] {
+            let sudoku = Sudoku::parse(text).unwrap();
+            for solver in solvers(SolverConfig::default()) {
+                let count = solver.count_solutions(&sudoku, 2);
+                assert_eq!(count.count, 0, "{} {}", solver.engine(), text);
+                assert!(!count.is_unique() && !count.is_capped());
+                assert!(count.solutions.is_empty());
+                assert_eq!(count.to_string(), "0");
+            }
+        }
+        // conflicting givens are not searched at all
+        let sudoku = Sudoku::parse(CONFLICTING).unwrap();
+        for solver in solvers(SolverConfig::default()) {
+            assert_eq!(
+                solver.count_solutions(&sudoku, 2).stats,
+                SolveStats::default()
+            );
+            assert!(matches!(solver.solve(&sudoku).outcome, Outcome::Invalid(_)));
+        }
+    }
+
+    #[test]
+    fn interrupted_searches() {
+        let sudoku = Sudoku::parse(UNIQUE).unwrap();
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        let configs = [
+            (
+                SolverConfig {
+                    timeout: Some(Duration::ZERO),
+                    ..SolverConfig::default()
+                },
+                Interruption::Timeout,
+            ),
+            (
+                SolverConfig {
+                    cancellation: Some(cancellation),
+                    ..SolverConfig::default()
+                },
+                Interruption::Cancelled,
+            ),
+        ];
+        for (config, interruption) in configs {
+            for solver in solvers(config.clone()) {
+                let result = solver.solve(&sudoku);
+                assert_eq!(result.outcome, Outcome::Interrupted(interruption));
+                let count = solver.count_solutions(&sudoku, 2);
+                assert_eq!(count.interrupted, Some(interruption));
+                assert!(!count.is_unique());
+                assert_eq!(count.to_string(), format!("{}+", count.count));
+            }
+        }
+    }
+
+    #[test]
+    fn interrupted_counts_are_not_unique() {
+        let count = |interrupted| SolutionCount::<Sudoku> {
+            count: 1,
+            limit: 2,
+            solutions: vec![],
+            stats: SolveStats::default(),
+            interrupted,
+        };
+        assert!(count(None).is_unique());
+        assert!(!count(Some(Interruption::Timeout)).is_unique());
+        assert!(!count(Some(Interruption::Cancelled)).is_unique());
+    }
+}
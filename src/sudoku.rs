@@ -0,0 +1,96 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::grid::{
+    find_conflicts, format_grid, is_grid_complete_full, parse_grid, parse_pencil_marks, CellValue,
+    Conflict, Grid, ParseError,
+};
+
+/// A 9x9 sudoku, either a puzzle with empty cells or a solved grid.
+///
+/// Cells are indexed from 0 to 80, row by row.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sudoku {
+    grid: Grid,
+}
+
+impl Sudoku {
+    /// Parses a sudoku written with digits `1`-`9` and `0`, `_` or `.` for
+    /// empty cells. Whitespace is ignored, so the grid can span several
+    /// lines.
+    pub fn parse(grid_string: &str) -> Result<Sudoku, ParseError> {
+        parse_grid(grid_string).map(Sudoku::from_grid)
+    }
+
+    /// Parses a sudoku in pencil marks: each cell is its value, or its
+    /// candidates as a group of digits or between parentheses. See
+    /// [`Sudoku::to_string_with_possibilities`] for the way back.
+    pub fn parse_pencil_marks(grid_string: &str) -> Result<Sudoku, ParseError> {
+        parse_pencil_marks(grid_string).map(Sudoku::from_grid)
+    }
+
+    pub fn from_grid(grid: Grid) -> Sudoku {
+        Sudoku { grid }
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn cell(&self, index: usize) -> CellValue {
+        self.grid.cell(index)
+    }
+
+    /// Number of cells holding a value.
+    pub fn clue_count(&self) -> usize {
+        self.grid.value_count()
+    }
+
+    /// Pairs of givens holding the same value in a row, column or box.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        find_conflicts(&self.grid)
+    }
+
+    /// Returns true when every cell holds a value and no row, column or block
+    /// contains a duplicate.
+    pub fn is_complete(&self) -> bool {
+        is_grid_complete_full(self.grid)
+    }
+
+    /// The sudoku as a single line of 81 characters, `.` for empty cells.
+    pub fn to_line(&self) -> String {
+        (0..81)
+            .map(|index| match self.grid.value(index) {
+                Some(v) => char::from(b'1' + v as u8),
+                None => '.',
+            })
+            .collect()
+    }
+
+    /// The sudoku over nine lines, empty cells showing their remaining
+    /// possibilities between parentheses. [`Sudoku::parse_pencil_marks`]
+    /// reads it back.
+    pub fn to_string_with_possibilities(&self) -> String {
+        format_grid(&self.grid, true)
+    }
+}
+
+impl FromStr for Sudoku {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Sudoku, ParseError> {
+        Sudoku::parse(s)
+    }
+}
+
+impl From<Grid> for Sudoku {
+    fn from(grid: Grid) -> Sudoku {
+        Sudoku::from_grid(grid)
+    }
+}
+
+impl fmt::Display for Sudoku {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_grid(&self.grid, false))
+    }
+}